@@ -1,12 +1,90 @@
+use std::io::Write;
 use std::ops;
 
 use zopfli::{self, Format, Options};
 
 use crate::error::*;
 
+/// Codec used to compress the control/data archive members of a `.deb`, plus any individually
+/// compressed asset (changelog, man pages). Selected via `--compress-type`/`--compress-level`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Widest compatibility; what every `dpkg` in the wild can unpack.
+    Gzip,
+    /// Smaller archives than gzip, supported since dpkg 1.17.
+    Xz,
+    /// Fastest to decompress, the default for `data.tar` since dpkg 1.21.
+    Zstd,
+    /// No compression at all; useful for already-compressed payloads or debugging.
+    None,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Xz
+    }
+}
+
+impl std::str::FromStr for Compression {
+    type Err = CargoDebError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gz" | "gzip" => Ok(Compression::Gzip),
+            "xz" => Ok(Compression::Xz),
+            "zst" | "zstd" => Ok(Compression::Zstd),
+            "none" => Ok(Compression::None),
+            other => Err(CargoDebError::UnknownCompressionType(other.to_owned())),
+        }
+    }
+}
+
+/// Tunable xz (LZMA2) parameters, configurable via `[package.metadata.deb]` and threaded down
+/// into [`compress`] instead of reading `num_cpus` inline. A larger `dict_size` (e.g. 64 MiB)
+/// noticeably shrinks large binaries beyond what the preset alone achieves, at the cost of more
+/// memory while compressing; capping `threads` makes output deterministic for CI.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct XzCompressionSettings {
+    /// Explicit LZMA2 dictionary/window size in bytes, overriding the preset's default.
+    /// Defaults to [`DEFAULT_XZ_DICT_SIZE`] when unset, which shrinks large stripped binaries
+    /// measurably further than the low/mid presets' own (much smaller) windows.
+    pub dict_size: Option<u32>,
+    /// Maximum number of compression threads; `None` uses every available core. `1` falls back
+    /// to single-threaded encoding (still via the same multithreaded stream API, just with one
+    /// block), which is deterministic and what reproducible builds should pin.
+    pub threads: Option<u32>,
+}
+
+/// Dictionary/window size used for xz compression unless `xz_compression.dict_size`/
+/// `--xz-dict-size` overrides it. Matches the window the top preset (`-9`) already uses, so every
+/// preset gets its benefit on compression ratio for large binaries, at the cost of more memory
+/// while compressing (and the same extra memory to decompress).
+pub const DEFAULT_XZ_DICT_SIZE: u32 = 64 * 1024 * 1024;
+
 pub enum Compressed {
     Gz(Vec<u8>),
     Xz(Vec<u8>),
+    Zst(Vec<u8>),
+    None(Vec<u8>),
+}
+
+impl Compressed {
+    /// File extension this compressed form uses in a `.deb`'s member names, e.g. `data.tar.xz`.
+    /// Empty for [`Compression::None`], whose member has no extension at all (`data.tar`).
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Gz(_) => "gz",
+            Self::Xz(_) => "xz",
+            Self::Zst(_) => "zst",
+            Self::None(_) => "",
+        }
+    }
+
+    /// Builds `<base>.<extension>` for this compressed form, or just `<base>` when uncompressed.
+    pub fn member_name(&self, base: &str) -> String {
+        let ext = self.extension();
+        if ext.is_empty() { base.to_owned() } else { format!("{}.{}", base, ext) }
+    }
 }
 
 impl ops::Deref for Compressed {
@@ -14,38 +92,81 @@ impl ops::Deref for Compressed {
 
     fn deref(&self) -> &Self::Target {
         match self {
-            Self::Gz(data) | Self::Xz(data) => &data,
+            Self::Gz(data) | Self::Xz(data) | Self::Zst(data) | Self::None(data) => &data,
         }
     }
 }
 
-/// Compresses data using the [native Rust implementation of Zopfli](https://github.com/carols10cents/zopfli).
-pub fn gz(data: &[u8]) -> CDResult<Vec<u8>> {
-    // Compressed data is typically half to a third the original size
-    let mut compressed = Vec::with_capacity(data.len() >> 1);
-    zopfli::compress(&Options::default(), &Format::Gzip, data, &mut compressed)?;
+/// Compresses data as gzip. `level` picks the backend: `None` uses the [native Rust
+/// implementation of Zopfli](https://github.com/carols10cents/zopfli), which produces the
+/// smallest output but is extremely slow on large payloads; `Some(level)` uses `flate2` at that
+/// quality (0-9) instead, which is far quicker and meant for fast iteration during development
+/// rather than release packaging.
+pub fn gz(data: &[u8], level: Option<u32>) -> CDResult<Vec<u8>> {
+    match level {
+        None => {
+            // Compressed data is typically half to a third the original size
+            let mut compressed = Vec::with_capacity(data.len() >> 1);
+            zopfli::compress(&Options::default(), &Format::Gzip, data, &mut compressed)?;
+            Ok(compressed)
+        },
+        Some(level) => {
+            use flate2::{Compression as Flate2Compression, GzBuilder};
 
-    Ok(compressed)
+            let mut compressed = Vec::with_capacity(data.len() >> 1);
+            {
+                let mut encoder = GzBuilder::new().write(&mut compressed, Flate2Compression::new(level.min(9)));
+                encoder.write_all(data)?;
+                encoder.finish()?;
+            }
+            Ok(compressed)
+        },
+    }
+}
+
+/// Single entry point for every codec this crate supports. `level` is the format-specific
+/// quality knob: xz preset 0-9, zstd level 1-19, or (when set) the `flate2` quality to use for
+/// a fast gzip instead of the default zopfli. Ignored for `None`. `xz` carries the xz-only
+/// dictionary size/thread cap, and is ignored by every other format.
+pub fn compress(format: Compression, level: Option<u32>, xz: &XzCompressionSettings, data: &[u8]) -> CDResult<Compressed> {
+    match format {
+        Compression::Gzip => gz(data, level).map(Compressed::Gz),
+        Compression::Xz => compress_with_xz(data, level, xz),
+        Compression::Zstd => zst(data, level),
+        Compression::None => Ok(Compressed::None(data.to_vec())),
+    }
 }
 
 /// Compresses data using the xz2 library
 #[cfg(feature = "lzma")]
-pub fn xz_or_gz(data: &[u8], fast: bool) -> CDResult<Compressed> {
+fn compress_with_xz(data: &[u8], level: Option<u32>, xz: &XzCompressionSettings) -> CDResult<Compressed> {
     use xz2::stream;
 
     // Compressed data is typically half to a third the original size
     let mut compressed = Vec::with_capacity(data.len() >> 1);
 
     // Compression level 6 is a good trade off between size and [ridiculously] long compression time
+    let preset = level.unwrap_or(6).min(9);
+    // The extreme variant of the top preset spends more CPU time searching for matches to get a
+    // slightly smaller result; the dictionary size is set separately below.
+    let preset = if preset >= 9 { preset | stream::LZMA_PRESET_EXTREME } else { preset };
+
+    let mut lzma_options = stream::LzmaOptions::new_preset(preset)
+        .map_err(CargoDebError::LzmaCompressionError)?;
+    lzma_options.dict_size(xz.dict_size.unwrap_or(DEFAULT_XZ_DICT_SIZE));
+    let mut filters = stream::Filters::new();
+    filters.lzma2(&lzma_options);
+
+    let threads = xz.threads.unwrap_or_else(|| num_cpus::get() as u32);
     let mut encoder = stream::MtStreamBuilder::new()
-        .threads(num_cpus::get() as u32)
-        .preset(if fast { 1 } else { 6 })
+        .threads(threads)
+        .filters(filters)
         .encoder()
-        .map_err(|e| CargoDebError::LzmaCompressionError(e))?;
+        .map_err(CargoDebError::LzmaCompressionError)?;
 
     encoder
         .process_vec(data, &mut compressed, stream::Action::Finish)
-        .map_err(|e| CargoDebError::LzmaCompressionError(e))?;
+        .map_err(CargoDebError::LzmaCompressionError)?;
 
     compressed.shrink_to_fit();
 
@@ -53,6 +174,193 @@ pub fn xz_or_gz(data: &[u8], fast: bool) -> CDResult<Compressed> {
 }
 
 #[cfg(not(feature = "lzma"))]
-pub fn xz_or_gz(data: &[u8], _fast: bool) -> CDResult<Compressed> {
-    gz(data).map(Compressed::Gz)
+fn compress_with_xz(data: &[u8], _level: Option<u32>, _xz: &XzCompressionSettings) -> CDResult<Compressed> {
+    gz(data, None).map(Compressed::Gz)
+}
+
+/// Compresses data using the zstd library. `level` is the zstd compression level (1-19),
+/// defaulting to 19 (the highest ratio).
+#[cfg(feature = "zstd")]
+pub fn zst(data: &[u8], level: Option<u32>) -> CDResult<Compressed> {
+    let level = level.unwrap_or(19).min(19) as i32;
+    let compressed = zstd::encode_all(data, level).map_err(CargoDebError::ZstdCompressionError)?;
+    Ok(Compressed::Zst(compressed))
+}
+
+#[cfg(not(feature = "zstd"))]
+pub fn zst(data: &[u8], _level: Option<u32>) -> CDResult<Compressed> {
+    gz(data, None).map(Compressed::Gz)
+}
+
+/// Compresses data with xz, or gzip when `fast` is set (or the `lzma` feature is disabled).
+/// Kept for callers that only care about the fast/slow toggle rather than picking a codec.
+pub fn xz_or_gz(data: &[u8], fast: bool) -> CDResult<Compressed> {
+    if fast {
+        gz(data, Some(1)).map(Compressed::Gz)
+    } else {
+        compress_with_xz(data, Some(6), &XzCompressionSettings::default())
+    }
+}
+
+/// Counts the bytes written through it before forwarding them on, so a [`CompressingWriter`] can
+/// report how big the archive was before compression without needing to buffer it.
+pub struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+
+    fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Streaming counterpart to [`compress`]. Wraps a writer so tar entries are compressed as they're
+/// appended instead of buffering the whole archive first, bounding peak memory to roughly one
+/// file at a time. Zopfli, the default (best-ratio) gzip backend, has no incremental API, so that
+/// one case still buffers its input internally and compresses it all at `finish`.
+pub enum CompressingWriter<W: Write> {
+    Gz(CountingWriter<flate2::write::GzEncoder<W>>),
+    GzZopfli { buffer: CountingWriter<Vec<u8>>, writer: W },
+    #[cfg(feature = "lzma")]
+    Xz(CountingWriter<xz2::write::XzEncoder<W>>),
+    #[cfg(feature = "zstd")]
+    Zstd(CountingWriter<zstd::stream::write::Encoder<'static, W>>),
+    None(CountingWriter<W>),
+}
+
+impl<W: Write> Write for CompressingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Gz(w) => w.write(buf),
+            Self::GzZopfli { buffer, .. } => buffer.write(buf),
+            #[cfg(feature = "lzma")]
+            Self::Xz(w) => w.write(buf),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(w) => w.write(buf),
+            Self::None(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Gz(w) => w.flush(),
+            Self::GzZopfli { buffer, .. } => buffer.flush(),
+            #[cfg(feature = "lzma")]
+            Self::Xz(w) => w.flush(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(w) => w.flush(),
+            Self::None(w) => w.flush(),
+        }
+    }
+}
+
+/// Builds a [`CompressingWriter`] for `format`, mirroring [`compress`]'s codec selection and
+/// feature-flag fallbacks, but writing straight into `inner` instead of returning a buffer.
+pub fn writer<W: Write>(format: Compression, level: Option<u32>, xz: &XzCompressionSettings, inner: W) -> CDResult<CompressingWriter<W>> {
+    match format {
+        Compression::Gzip => gz_writer(inner, level),
+        Compression::Xz => xz_writer(inner, level, xz),
+        Compression::Zstd => zstd_writer(inner, level),
+        Compression::None => Ok(CompressingWriter::None(CountingWriter::new(inner))),
+    }
+}
+
+fn gz_writer<W: Write>(inner: W, level: Option<u32>) -> CDResult<CompressingWriter<W>> {
+    match level {
+        None => Ok(CompressingWriter::GzZopfli { buffer: CountingWriter::new(Vec::new()), writer: inner }),
+        Some(level) => {
+            use flate2::{Compression as Flate2Compression, write::GzEncoder};
+            Ok(CompressingWriter::Gz(CountingWriter::new(GzEncoder::new(inner, Flate2Compression::new(level.min(9))))))
+        },
+    }
+}
+
+#[cfg(feature = "lzma")]
+fn xz_writer<W: Write>(inner: W, level: Option<u32>, xz: &XzCompressionSettings) -> CDResult<CompressingWriter<W>> {
+    use xz2::stream;
+    use xz2::write::XzEncoder;
+
+    let preset = level.unwrap_or(6).min(9);
+    let preset = if preset >= 9 { preset | stream::LZMA_PRESET_EXTREME } else { preset };
+
+    let mut lzma_options = stream::LzmaOptions::new_preset(preset)
+        .map_err(CargoDebError::LzmaCompressionError)?;
+    lzma_options.dict_size(xz.dict_size.unwrap_or(DEFAULT_XZ_DICT_SIZE));
+    let mut filters = stream::Filters::new();
+    filters.lzma2(&lzma_options);
+
+    let threads = xz.threads.unwrap_or_else(|| num_cpus::get() as u32);
+    let stream = stream::MtStreamBuilder::new()
+        .threads(threads)
+        .filters(filters)
+        .encoder()
+        .map_err(CargoDebError::LzmaCompressionError)?;
+
+    Ok(CompressingWriter::Xz(CountingWriter::new(XzEncoder::new_stream(inner, stream))))
+}
+
+#[cfg(not(feature = "lzma"))]
+fn xz_writer<W: Write>(inner: W, _level: Option<u32>, _xz: &XzCompressionSettings) -> CDResult<CompressingWriter<W>> {
+    gz_writer(inner, None)
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_writer<W: Write>(inner: W, level: Option<u32>) -> CDResult<CompressingWriter<W>> {
+    let level = level.unwrap_or(19).min(19) as i32;
+    let encoder = zstd::stream::write::Encoder::new(inner, level).map_err(CargoDebError::ZstdCompressionError)?;
+    Ok(CompressingWriter::Zstd(CountingWriter::new(encoder)))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_writer<W: Write>(inner: W, _level: Option<u32>) -> CDResult<CompressingWriter<W>> {
+    gz_writer(inner, None)
+}
+
+impl CompressingWriter<Vec<u8>> {
+    /// Finalizes compression and returns the finished member bytes plus the uncompressed size
+    /// (used for the "compressed/original ratio" log line).
+    pub fn finish(self) -> CDResult<(Compressed, u64)> {
+        Ok(match self {
+            Self::Gz(w) => {
+                let original = w.count;
+                (Compressed::Gz(w.into_inner().finish()?), original)
+            },
+            Self::GzZopfli { buffer, mut writer } => {
+                let original = buffer.count;
+                zopfli::compress(&Options::default(), &Format::Gzip, &buffer.into_inner(), &mut writer)?;
+                (Compressed::Gz(writer), original)
+            },
+            #[cfg(feature = "lzma")]
+            Self::Xz(w) => {
+                let original = w.count;
+                (Compressed::Xz(w.into_inner().finish()?), original)
+            },
+            #[cfg(feature = "zstd")]
+            Self::Zstd(w) => {
+                let original = w.count;
+                (Compressed::Zst(w.into_inner().finish().map_err(CargoDebError::ZstdCompressionError)?), original)
+            },
+            Self::None(w) => {
+                let original = w.count;
+                (Compressed::None(w.into_inner()), original)
+            },
+        })
+    }
 }
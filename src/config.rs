@@ -5,17 +5,25 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 pub struct CargoConfig {
+    /// Path of the nearest (highest-precedence) config file that was found, kept around for
+    /// error messages that point the user at "the" config file.
     path: PathBuf,
     config: toml::Value,
 }
 
 impl CargoConfig {
+    /// Mirrors `cargo`'s own config discovery: walks from `project_path` up to the filesystem
+    /// root, then `$CARGO_HOME`, then `/etc`, collecting every `.cargo/config[.toml]` found along
+    /// the way and deep-merging them, with files closer to `project_path` taking precedence over
+    /// more distant ones key-by-key.
     #[allow(deprecated)]
     pub fn new<P: AsRef<Path>>(project_path: P) -> CDResult<Option<Self>> {
+        let mut found = Vec::new();
+
         let mut project_path = project_path.as_ref();
         loop {
             if let Some(conf) = Self::try_parse(project_path)? {
-                return Ok(Some(conf));
+                found.push(conf);
             }
             if let Some(parent) = project_path.parent() {
                 project_path = parent;
@@ -23,17 +31,31 @@ impl CargoConfig {
                 break;
             }
         }
-        if let Some(home) = env::home_dir() {
-            if let Some(conf) = Self::try_parse(&home)? {
-                return Ok(Some(conf));
+        if let Some(cargo_home) = env::var_os("CARGO_HOME").map(PathBuf::from).or_else(env::home_dir) {
+            if let Some(conf) = Self::try_parse(&cargo_home)? {
+                found.push(conf);
             }
         }
         if let Some(conf) = Self::try_parse("/etc")? {
-            return Ok(Some(conf));
+            found.push(conf);
+        }
+
+        let mut found = found.into_iter();
+        let mut merged = match found.next() {
+            Some(nearest) => nearest,
+            None => return Ok(None),
+        };
+        for farther in found {
+            merged.merge_from(farther);
         }
-        Ok(None)
+        Ok(Some(merged))
     }
 
+    /// Merges `other`, a config file farther from the project root, into `self`; keys already
+    /// present in `self` win, filling in only what's missing, the same way `cargo` layers configs.
+    fn merge_from(&mut self, other: Self) {
+        merge_toml_value(&mut self.config, other.config);
+    }
 
     fn try_parse<P: AsRef<Path>>(path: P) -> CDResult<Option<Self>> {
         if path.as_ref().join(".cargo/config").exists() {
@@ -121,6 +143,54 @@ impl CargoConfig {
     }
 }
 
+/// Deep-merges `nearer`-wins `toml::Value` tables: recurses into nested tables so a `[target.x]`
+/// block in a farther config can still contribute keys a nearer config's `[target.x]` doesn't set,
+/// while any key present in `nearer` always wins outright (including non-table values).
+fn merge_toml_value(nearer: &mut toml::Value, farther: toml::Value) {
+    match (nearer, farther) {
+        (toml::Value::Table(nearer), toml::Value::Table(farther)) => {
+            for (key, farther_value) in farther {
+                match nearer.entry(key) {
+                    toml::map::Entry::Vacant(slot) => {
+                        slot.insert(farther_value);
+                    },
+                    toml::map::Entry::Occupied(mut slot) => {
+                        merge_toml_value(slot.get_mut(), farther_value);
+                    },
+                }
+            }
+        },
+        // Non-table values (or a type mismatch between the two configs) keep whatever `nearer`
+        // already had; it always takes precedence.
+        _ => {},
+    }
+}
+
+#[test]
+fn merge_fills_in_missing_keys_from_farther_config() {
+    let mut nearer = CargoConfig::from_str(r#"
+[target.foo]
+linker = "near-ld"
+"#, "near".into()).unwrap();
+    let farther = CargoConfig::from_str(r#"
+[target.foo]
+linker = "far-ld"
+strip = "far-strip"
+
+[target.bar]
+linker = "bar-ld"
+"#, "far".into()).unwrap();
+
+    nearer.merge_from(farther);
+
+    // The nearer config's own key always wins...
+    assert_eq!("near-ld", nearer.linker_command("foo").unwrap());
+    // ...but a key it didn't set is filled in from the farther config...
+    assert_eq!("far-strip", nearer.strip_command("foo").unwrap());
+    // ...including an entire `[target.*]` table the nearer config never mentioned at all.
+    assert_eq!("bar-ld", nearer.linker_command("bar").unwrap());
+}
+
 #[test]
 fn parse_strip() {
     let c = CargoConfig::from_str(r#"
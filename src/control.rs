@@ -1,3 +1,5 @@
+use crate::compress::Compressed;
+use crate::dh_installinit;
 use crate::dh_installsystemd;
 use crate::dh_lib;
 use crate::error::*;
@@ -14,11 +16,16 @@ use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
-/// Generates an uncompressed tar archive with `control`, `md5sums`, and others
-pub fn generate_archive(options: &Config, time: u64, asset_hashes: HashMap<PathBuf, Digest>, listener: &mut dyn Listener) -> CDResult<Vec<u8>> {
-    let mut archive = Archive::new(time);
+/// Generates the control archive (`control`, `md5sums`, and others), compressing each entry as
+/// it's appended rather than buffering the whole tar first.
+pub fn generate_archive(options: &Config, time: u64, asset_hashes: HashMap<PathBuf, Digest>, listener: &mut dyn Listener) -> CDResult<Compressed> {
+    let writer = crate::compress::writer(options.compress_type, options.compress_level, &options.xz_compression, Vec::new())?;
+    let mut archive = Archive::new(time, writer);
     generate_md5sums(&mut archive, options, asset_hashes)?;
     generate_control(&mut archive, options, listener)?;
+    if !options.shlibs.is_empty() {
+        generate_shlibs(&mut archive, options)?;
+    }
     if let Some(ref files) = options.conf_files {
         generate_conf_files(&mut archive, files)?;
     }
@@ -26,54 +33,84 @@ pub fn generate_archive(options: &Config, time: u64, asset_hashes: HashMap<PathB
     if let Some(ref file) = options.triggers_file {
         generate_triggers_file(&mut archive, file)?;
     }
-    Ok(archive.into_inner()?)
+    generate_autopkgtest_control(&mut archive, options)?;
+    let (compressed, _original_size) = archive.into_inner()?.finish()?;
+    Ok(compressed)
 }
 
-/// Append Debian maintainer script files (control, preinst, postinst, prerm,
-/// postrm and templates) present in the `maintainer_scripts` path to the
-/// archive, if `maintainer_scripts` is configured.
+/// Computes the `preinst`/`postinst`/`prerm`/`postrm` content that `generate_scripts` would
+/// append to the archive, without touching the archive itself. Used by `generate_scripts` and,
+/// as a read-only inspection step, by `cargo deb --diff`.
 ///
-/// Additionally, when `systemd_units` is configured, shell script fragments
-/// "for enabling, disabling, starting, stopping and restarting systemd unit
-/// files" (quoting man 1 dh_installsystemd) will replace the `#DEBHELPER#`
-/// token in the provided maintainer scripts.
+/// When `systemd_units` is configured, shell script fragments "for enabling, disabling,
+/// starting, stopping and restarting systemd unit files" (quoting man 1 dh_installsystemd) will
+/// replace the `#DEBHELPER#` token in the provided maintainer scripts.
 ///
-/// If a shell fragment cannot be inserted because the target script is missing
-/// then the entire script will be generated and appended to the archive.
+/// If a shell fragment cannot be inserted because the target script is missing then the entire
+/// script will be generated instead.
 ///
 /// # Requirements
 ///
 /// When `systemd_units` is configured, user supplied `maintainer_scripts` must
 /// contain a `#DEBHELPER#` token at the point where shell script fragments
 /// should be inserted.
-fn generate_scripts(archive: &mut Archive, option: &Config, listener: &mut dyn Listener) -> CDResult<()> {
+pub(crate) fn generate_maintainer_scripts(option: &Config, listener: &mut dyn Listener) -> CDResult<ScriptFragments> {
+    let systemd_units_config = match &option.systemd_units {
+        Some(config) => config,
+        None => return Ok(ScriptFragments::with_capacity(0)),
+    };
+    let maintainer_scripts_dir = match &option.maintainer_scripts {
+        Some(dir) => dir,
+        None => return Ok(ScriptFragments::with_capacity(0)),
+    };
+
+    // Select and populate autoscript templates relevant to any etc/init.d/ SysV init scripts in
+    // this package. Generated first so that, for a script sharing a base name with a systemd
+    // unit below, its guarded fragments are prepended ahead of (and so run before) the
+    // unconditional systemd fragments, matching upstream dh_installinit/dh_installsystemd
+    // coordination.
+    let mut scripts = dh_installinit::generate(
+        &option.name,
+        &option.assets.resolved,
+        &dh_installinit::Options::from(systemd_units_config),
+        listener)?;
+
+    // Select and populate autoscript templates relevant to the unit
+    // file(s) in this package and the configuration settings chosen.
+    for (script_name, content) in dh_installsystemd::generate(
+        &option.name,
+        &option.assets.resolved,
+        &dh_installsystemd::Options::from(systemd_units_config),
+        listener)?
+    {
+        scripts.entry(script_name).or_default().extend(content);
+    }
+
+    // Get Option<&str> from Option<String>
+    let unit_name = systemd_units_config.unit_name
+        .as_deref();
+
+    // Replace the #DEBHELPER# token in the users maintainer scripts
+    // and/or generate maintainer scripts from scratch as needed.
+    dh_lib::apply(
+        maintainer_scripts_dir,
+        &option.deb_temp_dir().join("fingerprint"),
+        &option.maintainer_scripts_rerun_if_changed,
+        &mut scripts,
+        &option.name,
+        unit_name,
+        listener)?;
+
+    Ok(scripts)
+}
+
+/// Append Debian maintainer script files (control, preinst, postinst, prerm,
+/// postrm and templates) present in the `maintainer_scripts` path to the
+/// archive, if `maintainer_scripts` is configured, preferring the generated
+/// versions from `generate_maintainer_scripts` where available.
+fn generate_scripts<W: Write>(archive: &mut Archive<W>, option: &Config, listener: &mut dyn Listener) -> CDResult<()> {
     if let Some(ref maintainer_scripts_dir) = option.maintainer_scripts {
-        let mut scripts;
-
-        if let Some(systemd_units_config) = &option.systemd_units {
-            // Select and populate autoscript templates relevant to the unit
-            // file(s) in this package and the configuration settings chosen.
-            scripts = dh_installsystemd::generate(
-                &option.name,
-                &option.assets.resolved,
-                &dh_installsystemd::Options::from(systemd_units_config),
-                listener)?;
-
-            // Get Option<&str> from Option<String>
-            let unit_name = systemd_units_config.unit_name
-                .as_deref();
-
-            // Replace the #DEBHELPER# token in the users maintainer scripts
-            // and/or generate maintainer scripts from scratch as needed.
-            dh_lib::apply(
-                &maintainer_scripts_dir,
-                &mut scripts,
-                &option.name,
-                unit_name,
-                listener)?;
-        } else {
-            scripts = ScriptFragments::with_capacity(0);
-        }
+        let mut scripts = generate_maintainer_scripts(option, listener)?;
 
         // Add maintainer scripts to the archive, either those supplied by the
         // user or if available prefer modified versions generated above.
@@ -97,7 +134,7 @@ fn generate_scripts(archive: &mut Archive, option: &Config, listener: &mut dyn L
 }
 
 /// Creates the md5sums file which contains a list of all contained files and the md5sums of each.
-fn generate_md5sums(archive: &mut Archive, options: &Config, asset_hashes: HashMap<PathBuf, Digest>) -> CDResult<()> {
+fn generate_md5sums<W: Write>(archive: &mut Archive<W>, options: &Config, asset_hashes: HashMap<PathBuf, Digest>) -> CDResult<()> {
     let mut md5sums: Vec<u8> = Vec::new();
 
     // Collect md5sums from each asset in the archive (excludes symlinks).
@@ -117,7 +154,7 @@ fn generate_md5sums(archive: &mut Archive, options: &Config, asset_hashes: HashM
 }
 
 /// Generates the control file that obtains all the important information about the package.
-fn generate_control(archive: &mut Archive, options: &Config, listener: &mut dyn Listener) -> CDResult<()> {
+fn generate_control<W: Write>(archive: &mut Archive<W>, options: &Config, listener: &mut dyn Listener) -> CDResult<()> {
     // Create and return the handle to the control file with write access.
     let mut control: Vec<u8> = Vec::with_capacity(1024);
 
@@ -130,7 +167,10 @@ fn generate_control(archive: &mut Archive, options: &Config, listener: &mut dyn
             writeln!(&mut control, "Vcs-Browser: {}", repo)?;
         }
         if let Some(kind) = options.repository_type() {
-            writeln!(&mut control, "Vcs-{}: {}", kind, repo)?;
+            match (kind, options.vcs_info.as_ref()) {
+                ("Git", Some(vcs)) => writeln!(&mut control, "Vcs-Git: {} #{}{}", repo, vcs.commit, if vcs.dirty { " (dirty)" } else { "" })?,
+                _ => writeln!(&mut control, "Vcs-{}: {}", kind, repo)?,
+            }
         }
     }
     if let Some(homepage) = options.homepage.as_ref().or(options.documentation.as_ref()) {
@@ -142,6 +182,15 @@ fn generate_control(archive: &mut Archive, options: &Config, listener: &mut dyn
     writeln!(&mut control, "Priority: {}", options.priority)?;
     control.write_all(b"Standards-Version: 3.9.4\n")?;
     writeln!(&mut control, "Maintainer: {}", options.maintainer)?;
+    if let Some(ref auto_built) = options.auto_built_package {
+        writeln!(&mut control, "Auto-Built-Package: {}", auto_built)?;
+    }
+    if let Some(ref autopkgtest) = options.autopkgtest {
+        control.write_all(b"Testsuite: autopkgtest\n")?;
+        if !autopkgtest.triggers.is_empty() {
+            writeln!(&mut control, "Testsuite-Triggers: {}", autopkgtest.triggers.join(", "))?;
+        }
+    }
 
     let installed_size = options.assets.resolved
         .iter()
@@ -155,6 +204,10 @@ fn generate_control(archive: &mut Archive, options: &Config, listener: &mut dyn
         writeln!(&mut control, "Depends: {}", deps)?;
     }
 
+    if let Some(ref recommends) = options.recommends {
+        writeln!(&mut control, "Recommends: {}", recommends)?;
+    }
+
     if let Some(ref build_depends) = options.build_depends {
         writeln!(&mut control, "Build-Depends: {}", build_depends)?;
     }
@@ -189,8 +242,20 @@ fn generate_control(archive: &mut Archive, options: &Config, listener: &mut dyn
     Ok(())
 }
 
+/// Writes the Debian `shlibs` file, mapping each SONAME-versioned shared library this
+/// package installs to a minimum-version `Depends` entry, so `dpkg-shlibdeps` run against
+/// packages linking against this library resolves a correctly versioned dependency.
+fn generate_shlibs<W: Write>(archive: &mut Archive<W>, options: &Config) -> CDResult<()> {
+    let mut shlibs: Vec<u8> = Vec::new();
+    for line in &options.shlibs {
+        writeln!(shlibs, "{}", line)?;
+    }
+    archive.file("./shlibs", &shlibs, 0o644)?;
+    Ok(())
+}
+
 /// If configuration files are required, the conffiles file will be created.
-fn generate_conf_files(archive: &mut Archive, files: &str) -> CDResult<()> {
+fn generate_conf_files<W: Write>(archive: &mut Archive<W>, files: &str) -> CDResult<()> {
     let mut data = Vec::new();
     data.write_all(files.as_bytes())?;
     data.push(b'\n');
@@ -198,13 +263,56 @@ fn generate_conf_files(archive: &mut Archive, files: &str) -> CDResult<()> {
     Ok(())
 }
 
-fn generate_triggers_file(archive: &mut Archive, path: &Path) -> CDResult<()> {
+fn generate_triggers_file<W: Write>(archive: &mut Archive<W>, path: &Path) -> CDResult<()> {
     if let Ok(content) = fs::read(path) {
         archive.file("./triggers", &content, 0o644)?;
     }
     Ok(())
 }
 
+/// Generates a `debian/tests/control`-equivalent autopkgtest definition from
+/// `[package.metadata.deb.autopkgtest]`, so the `.deb`/`.changes` this produces is directly
+/// consumable by the Debian CI harness. One `Test-Command:` stanza per configured command, plus
+/// (unless `skip_systemd_smoke_test`) a built-in stanza that `systemctl status`-checks every
+/// system unit this package installs under [`dh_installsystemd::LIB_SYSTEMD_SYSTEM_DIR`]; user
+/// units are not checked.
+fn generate_autopkgtest_control<W: Write>(archive: &mut Archive<W>, options: &Config) -> CDResult<()> {
+    let autopkgtest = match &options.autopkgtest {
+        Some(autopkgtest) => autopkgtest,
+        None => return Ok(()),
+    };
+
+    let mut control: Vec<u8> = Vec::new();
+    for command in &autopkgtest.test_commands {
+        if !control.is_empty() {
+            control.push(b'\n');
+        }
+        writeln!(&mut control, "Test-Command: {}", command)?;
+        writeln!(&mut control, "Restrictions: allow-stderr")?;
+    }
+
+    if !autopkgtest.skip_systemd_smoke_test {
+        let units: Vec<String> = options.assets.resolved
+            .iter()
+            .filter(|a| a.target_path.starts_with(dh_installsystemd::LIB_SYSTEMD_SYSTEM_DIR))
+            .map(|a| crate::util::fname_from_path(a.target_path.as_path()))
+            .collect();
+
+        if !units.is_empty() {
+            if !control.is_empty() {
+                control.push(b'\n');
+            }
+            writeln!(&mut control, "Test-Command: set -e; for unit in {}; do systemctl status \"$unit\"; done", units.join(" "))?;
+            writeln!(&mut control, "Restrictions: allow-stderr, needs-root, isolation-machine")?;
+        }
+    }
+
+    if !control.is_empty() {
+        archive.file("./tests/control", &control, 0o644)?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -233,7 +341,7 @@ mod tests {
         out
     }
 
-    fn prepare() -> (Config, crate::listener::MockListener, Archive) {
+    fn prepare() -> (Config, crate::listener::MockListener, Archive<Vec<u8>>) {
         let mut mock_listener = crate::listener::MockListener::new();
         mock_listener.expect_info().return_const(());
 
@@ -245,9 +353,10 @@ mod tests {
             None,
             None,
             &mut mock_listener,
+            "release",
         ).unwrap();
 
-        let ar = Archive::new(0);
+        let ar = Archive::new(0, Vec::new());
 
         (config, mock_listener, ar)
     }
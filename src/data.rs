@@ -1,3 +1,4 @@
+use crate::compress::{self, Compressed};
 use crate::error::*;
 use crate::listener::Listener;
 use crate::manifest::{Asset, Config};
@@ -8,34 +9,31 @@ use std::fmt;
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use zopfli::{self, Format, Options};
 
-/// Generates an uncompressed tar archive and hashes of its files
-pub fn generate_archive(options: &Config, time: u64, listener: &mut dyn Listener) -> CDResult<(Vec<u8>, HashMap<PathBuf, Digest>)> {
-    let mut archive = Archive::new(time);
+/// Generates the data archive, compressing each entry as it's appended rather than buffering the
+/// whole tar first. Returns the compressed member, its uncompressed size (for the
+/// compressed/original ratio log line), and hashes of its files.
+pub fn generate_archive(options: &Config, time: u64, listener: &mut dyn Listener) -> CDResult<(Compressed, u64, HashMap<PathBuf, Digest>)> {
+    let writer = compress::writer(options.compress_type, options.compress_level, &options.xz_compression, Vec::new())?;
+    let mut archive = Archive::new(time, writer);
     let copy_hashes = archive_files(&mut archive, options, listener)?;
-    Ok((archive.into_inner()?, copy_hashes))
+    let (compressed, original_size) = archive.into_inner()?.finish()?;
+    Ok((compressed, original_size, copy_hashes))
 }
 
-/// Generates compressed changelog file
-pub(crate) fn generate_changelog_asset(options: &Config) -> CDResult<Option<Vec<u8>>> {
+/// Generates compressed changelog file, using `options.compress_type`/`compress_level`
+pub(crate) fn generate_changelog_asset(options: &Config) -> CDResult<Option<Compressed>> {
     if let Some(ref path) = options.changelog {
         let changelog = fs::read(options.path_in_workspace(path))
-            .and_then(|content| {
-                // The input is plaintext, but the debian package should contain gzipped one.
-                let mut compressed = Vec::with_capacity(content.len());
-                zopfli::compress(&Options::default(), &Format::Gzip, &content, &mut compressed)?;
-                compressed.shrink_to_fit();
-                Ok(compressed)
-            })
             .map_err(|e| CargoDebError::IoFile("unable to read changelog file", e, path.into()))?;
-        Ok(Some(changelog))
+        let compressed = crate::compress::compress(options.compress_type, options.compress_level, &options.xz_compression, &changelog)?;
+        Ok(Some(compressed))
     } else {
         Ok(None)
     }
 }
 
-fn append_copyright_metadata(copyright: &mut Vec<u8>, options: &Config) -> Result<(), CargoDebError> {
+fn append_copyright_metadata(copyright: &mut Vec<u8>, options: &Config, detected_license: Option<&str>) -> Result<(), CargoDebError> {
     writeln!(copyright, "Format: https://www.debian.org/doc/packaging-manuals/copyright-format/1.0/")?;
     writeln!(copyright, "Upstream-Name: {}", options.name)?;
     if let Some(source) = options.repository.as_ref().or(options.homepage.as_ref()) {
@@ -44,18 +42,44 @@ fn append_copyright_metadata(copyright: &mut Vec<u8>, options: &Config) -> Resul
     writeln!(copyright, "Copyright: {}", options.copyright)?;
     if let Some(ref license) = options.license {
         writeln!(copyright, "License: {}", license)?;
+    } else if let Some(license) = detected_license {
+        writeln!(copyright, "License: {}", license)?;
     }
     Ok(())
 }
 
+/// Warns through `listener` when the license file's fingerprinted text disagrees with the
+/// declared `license` field, or when it doesn't clearly match a known SPDX license at all.
+fn check_declared_license(declared: Option<&str>, detected: Option<&crate::license_detect::Match>, listener: &dyn Listener) {
+    if let Some(detected) = detected {
+        if !detected.is_confident() {
+            listener.warning(format!(
+                "license file doesn't clearly match a known SPDX license (closest guess: {}, {:.0}% confidence)",
+                detected.spdx_id, detected.confidence * 100.0,
+            ));
+        } else if let Some(declared) = declared {
+            if declared != detected.spdx_id {
+                listener.warning(format!(
+                    "declared license '{}' doesn't match the license file's text, which looks like {} ({:.0}% confidence)",
+                    declared, detected.spdx_id, detected.confidence * 100.0,
+                ));
+            }
+        }
+    }
+}
+
 /// Generates the copyright file from the license file and adds that to the tar archive.
-pub(crate) fn generate_copyright_asset(options: &Config) -> CDResult<Vec<u8>> {
+pub(crate) fn generate_copyright_asset(options: &Config, listener: &dyn Listener) -> CDResult<Vec<u8>> {
     let mut copyright: Vec<u8> = Vec::new();
     if let Some(ref path) = options.license_file {
         let license_string = fs::read_to_string(options.path_in_workspace(path))
             .map_err(|e| CargoDebError::IoFile("unable to read license file", e, path.to_owned()))?;
+
+        let detected = crate::license_detect::detect(&license_string);
+        check_declared_license(options.license.as_deref(), detected.as_ref(), listener);
+
         if !has_copyright_metadata(&license_string) {
-            append_copyright_metadata(&mut copyright, options)?;
+            append_copyright_metadata(&mut copyright, options, detected.as_ref().map(|m| m.spdx_id))?;
         }
 
         // Skip the first `A` number of lines and then iterate each line after that.
@@ -69,7 +93,11 @@ pub(crate) fn generate_copyright_asset(options: &Config) -> CDResult<Vec<u8>> {
             }
         }
     } else {
-        append_copyright_metadata(&mut copyright, options)?;
+        append_copyright_metadata(&mut copyright, options, None)?;
+    }
+
+    for notice in &options.dependency_license_notices {
+        append_dependency_license_paragraph(&mut copyright, notice)?;
     }
 
     // Write a copy to the disk for the sake of obtaining a md5sum for the control archive.
@@ -81,37 +109,93 @@ fn has_copyright_metadata(file: &str) -> bool {
         .any(|l| l.starts_with("License: ") || l.starts_with("Source: ") || l.starts_with("Upstream-Name: ") || l.starts_with("Format: "))
 }
 
-/// Compress man page assets per Debian Policy.
+/// Appends one DEP-5 `Files:`/`License:` paragraph documenting a dependency crate's license
+/// obligations, followed by the verbatim text of any license/notice/authors files it carries.
+fn append_dependency_license_paragraph(copyright: &mut Vec<u8>, notice: &crate::manifest::DependencyLicenseNotice) -> CDResult<()> {
+    writeln!(copyright)?;
+    writeln!(copyright, "Files: {}-{}/*", notice.name, notice.version)?;
+    writeln!(copyright, "Copyright: {} contributors", notice.name)?;
+    writeln!(copyright, "License: {}", notice.license.as_deref().unwrap_or("Unknown"))?;
+    for (file_name, text) in &notice.texts {
+        writeln!(copyright, " .")?;
+        writeln!(copyright, " [{}]", file_name)?;
+        for line in text.lines() {
+            if line.trim().is_empty() {
+                copyright.write_all(b" .\n")?;
+            } else {
+                copyright.write_all(b" ")?;
+                copyright.write_all(line.as_bytes())?;
+                copyright.write_all(b"\n")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// One rule in the [`DOC_COMPRESSION_RULES`] table: assets under `path_prefix` are compressed,
+/// unless smaller than `min_size` bytes (when set).
+struct DocCompressionRule {
+    path_prefix: &'static str,
+    min_size: Option<u64>,
+}
+
+/// Debian Policy and lintian expect man pages and info pages to always be gzip-compressed;
+/// oversized `usr/share/doc/<pkg>` files (changelogs, NEWS) are compressed too, but only past
+/// a size threshold so small ones aren't needlessly bloated with a `.gz`/`.xz`/`.zst` header.
 ///
 /// # References
 ///
 /// https://www.debian.org/doc/debian-policy/ch-docs.html#manual-pages
 /// https://lintian.debian.org/tags/manpage-not-compressed.html
-pub fn compress_man_pages(options: &mut Config, listener: &dyn Listener) -> CDResult<()> {
+const DOC_COMPRESSION_RULES: &[DocCompressionRule] = &[
+    DocCompressionRule { path_prefix: "usr/share/man/", min_size: None },
+    DocCompressionRule { path_prefix: "usr/share/info/", min_size: None },
+    DocCompressionRule { path_prefix: "usr/share/doc/", min_size: Some(4096) },
+];
+
+/// Compresses documentation assets (man pages, info pages, oversized doc files) per Debian
+/// Policy, using `options.compress_type`/`compress_level`. Skips assets that are already
+/// compressed, symlinks (so they keep pointing at the real, separately-compressed file), and
+/// anything matching `options.compress_doc_exclude`.
+pub fn compress_documentation(options: &mut Config, listener: &dyn Listener) -> CDResult<()> {
     let mut indices_to_remove = Vec::new();
     let mut new_assets = Vec::new();
 
     for (idx, asset) in options.assets.resolved.iter().enumerate() {
+        if matches!(asset.source, crate::manifest::AssetSource::Symlink(_)) {
+            continue;
+        }
+
         let target_path_str = asset.target_path.to_string_lossy();
-        if target_path_str.starts_with("usr/share/man/") &&
-           !target_path_str.ends_with(".gz")
-        {
-            listener.info(format!("Compressing '{}'", asset.source.path().unwrap_or(Path::new("-")).display()));
-
-            let content = asset.source.data()?;
-            let mut compressed = Vec::with_capacity(content.len());
-            zopfli::compress(&Options::default(), &Format::Gzip, &content, &mut compressed)?;
-            compressed.shrink_to_fit();
-
-            new_assets.push(Asset::new(
-                crate::manifest::AssetSource::Data(compressed),
-                Path::new(&format!("{}.gz", target_path_str)).into(),
-                asset.chmod,
-                false,
-            ));
+        if target_path_str.ends_with(".gz") || target_path_str.ends_with(".xz") || target_path_str.ends_with(".zst") {
+            continue;
+        }
+        if options.compress_doc_exclude.iter().any(|pat| pat.matches_path(&asset.target_path)) {
+            continue;
+        }
 
-            indices_to_remove.push(idx);
+        let rule = DOC_COMPRESSION_RULES.iter().find(|rule| target_path_str.starts_with(rule.path_prefix));
+        let applies = match rule {
+            Some(rule) => rule.min_size.map_or(true, |min_size| asset.source.len().unwrap_or(0) >= min_size),
+            None => false,
+        };
+        if !applies {
+            continue;
         }
+
+        listener.info(format!("Compressing '{}'", asset.source.path().unwrap_or(Path::new("-")).display()));
+
+        let content = asset.source.data()?;
+        let compressed = crate::compress::compress(options.compress_type, options.compress_level, &options.xz_compression, &content)?;
+
+        new_assets.push(Asset::new(
+            crate::manifest::AssetSource::Data(compressed.to_vec()),
+            Path::new(&compressed.member_name(&target_path_str)).into(),
+            asset.chmod,
+            false,
+        ));
+
+        indices_to_remove.push(idx);
     }
 
     for idx in indices_to_remove.iter().rev() {
@@ -125,8 +209,13 @@ pub fn compress_man_pages(options: &mut Config, listener: &dyn Listener) -> CDRe
 
 /// Copies all the files to be packaged into the tar archive.
 /// Returns MD5 hashes of files copied
-fn archive_files(archive: &mut Archive, options: &Config, listener: &mut dyn Listener) -> CDResult<HashMap<PathBuf, Digest>> {
+fn archive_files<W: Write>(archive: &mut Archive<W>, options: &Config, listener: &mut dyn Listener) -> CDResult<HashMap<PathBuf, Digest>> {
     let mut hashes = HashMap::new();
+    // Content hash (plus chmod/owner/capabilities, since a hardlink can't carry different
+    // permissions, ownership, or xattrs than the entry it points at) of every plain file archived
+    // so far, keyed to its archive path, so a later asset with identical content can be linked to
+    // it instead of stored again.
+    let mut seen_content: HashMap<([u8; 16], u32, Option<crate::manifest::Owner>, Option<Vec<u8>>), PathBuf> = HashMap::new();
     for asset in &options.assets.resolved {
         let out_data = asset.source.data()?;
 
@@ -142,20 +231,42 @@ fn archive_files(archive: &mut Archive, options: &Config, listener: &mut dyn Lis
         listener.info(log_line);
 
         let mut archived = false;
-        if options.preserve_symlinks {
+        if let crate::manifest::AssetSource::Symlink(ref link_name) = asset.source {
+            archived = true;
+            archive.symlink_with_owner(&asset.target_path, link_name, asset.owner.as_ref())?;
+        } else if options.preserve_symlinks {
             if let Some(source_path) = asset.source.path() {
                 let md = fs::symlink_metadata(source_path)?;
                 if md.file_type().is_symlink() {
                     archived = true;
                     let link_name = fs::read_link(source_path)?;
-                    archive.symlink(&asset.target_path, &link_name)?;
+                    archive.symlink_with_owner(&asset.target_path, &link_name, asset.owner.as_ref())?;
                 }
             }
         }
 
         if !archived {
-            hashes.insert(asset.target_path.clone(), md5::compute(&out_data));
-            archive.file(&asset.target_path, &out_data, asset.chmod)?;
+            let digest = md5::compute(&out_data);
+            hashes.insert(asset.target_path.clone(), digest);
+
+            let dedup_key = (digest.0, asset.chmod, asset.owner.clone(), asset.capabilities.clone());
+            if options.hardlink_dedup {
+                if let Some(link_name) = seen_content.get(&dedup_key) {
+                    archive.hardlink(&asset.target_path, link_name, asset.chmod, asset.owner.as_ref())?;
+                    continue;
+                }
+                seen_content.insert(dedup_key, asset.target_path.clone());
+            }
+
+            let capability_xattr;
+            let xattrs: &[(&str, &[u8])] = match asset.capabilities.as_deref() {
+                Some(caps) => {
+                    capability_xattr = [("security.capability", caps)];
+                    &capability_xattr
+                },
+                None => &[],
+            };
+            archive.file_with_xattrs(&asset.target_path, &out_data, asset.chmod, asset.owner.as_ref(), xattrs)?;
         }
     }
     Ok(hashes)
@@ -1,4 +1,4 @@
-use crate::error::CDResult;
+use crate::error::{CDResult, CargoDebError};
 use crate::manifest::Config;
 use crate::pathbytes::*;
 use ar::{Builder, Header};
@@ -16,15 +16,21 @@ pub struct DebArchive {
 
 impl DebArchive {
     pub fn new(config: &Config) -> CDResult<Self> {
-        let out_filename = format!("{}{sep}{}{sep}{}.deb", config.deb_name, config.deb_version, config.architecture,
-            sep = config.deb_name_separator.unwrap_or(DEFAULT_SEPARATOR)
-        );
+        let out_filename = config.deb_output_filename();
         let prefix = config.deb_temp_dir();
         let out_abspath = config.deb_output_path(&out_filename);
         {
             let deb_dir = out_abspath.parent().ok_or("invalid dir")?;
             let _ = fs::create_dir_all(deb_dir);
         }
+        // Fail early with a clear error, rather than letting the archiver discover it mid-write:
+        // a leftover .deb from a previous run (or any file at all) that's read-only would
+        // otherwise surface as an opaque "Permission denied" from `File::create` below.
+        if let Ok(metadata) = fs::metadata(&out_abspath) {
+            if metadata.permissions().readonly() {
+                return Err(CargoDebError::OutputNotWriteable(out_abspath));
+            }
+        }
         let ar_builder = Builder::new(File::create(&out_abspath)?);
 
         Ok(DebArchive {
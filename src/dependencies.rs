@@ -1,4 +1,7 @@
 use crate::error::*;
+use crate::listener::Listener;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::io::BufRead;
 use std::path::Path;
 use std::process::Command;
@@ -52,6 +55,71 @@ pub fn resolve(path: &Path) -> CDResult<Vec<String>> {
     Ok(deps)
 }
 
+/// Splits a `dpkg-shlibdeps` dependency atom like `"libc6 (>= 2.31)"` into its package name and,
+/// if present, its version constraint as (operator, version), e.g. `(">=", "2.31")`.
+fn split_dep_atom(atom: &str) -> (&str, Option<(&str, &str)>) {
+    match atom.find('(') {
+        Some(paren_idx) => {
+            let name = atom[..paren_idx].trim();
+            let constraint = atom[paren_idx + 1..].trim_end_matches(')').trim();
+            (name, constraint.split_once(' ').map(|(op, ver)| (op, ver.trim())))
+        },
+        None => (atom.trim(), None),
+    }
+}
+
+/// Compares two dotted numeric versions (e.g. `"2.31"` vs `"2.27"`) component-wise. Falls back to
+/// a plain string comparison for anything that isn't all-numeric, which is enough for the versions
+/// `dpkg-shlibdeps` itself emits (a full Debian version comparator would also need to handle `~`
+/// suffixes and epochs, neither of which shows up in its output).
+fn version_cmp(a: &str, b: &str) -> Ordering {
+    let parse = |v: &str| v.split('.').map(str::parse::<u64>).collect::<Result<Vec<_>, _>>();
+    match (parse(a), parse(b)) {
+        (Ok(a_parts), Ok(b_parts)) => a_parts.cmp(&b_parts),
+        _ => a.cmp(b),
+    }
+}
+
+/// Keeps whichever of `existing`/`new` atoms for the same package has the stronger version
+/// constraint (the higher required version), inserting `new` into `merged` only if it wins. If
+/// either atom has no version constraint, or their operators differ, keeps the one already in
+/// `merged` rather than guessing which operator is "stronger" across kinds.
+fn merge_dep_atom(merged: &mut HashMap<String, String>, name: String, new: String) {
+    if let Some(existing) = merged.get(&name) {
+        if let (Some((existing_op, existing_ver)), Some((new_op, new_ver))) = (split_dep_atom(existing).1, split_dep_atom(&new).1) {
+            if existing_op != new_op || version_cmp(new_ver, existing_ver) != Ordering::Greater {
+                return;
+            }
+        } else {
+            return;
+        }
+    }
+    merged.insert(name, new);
+}
+
+/// Resolves the union of shared-library dependencies across several binaries, running
+/// `dpkg-shlibdeps` once per path in `paths` instead of just one, and reconciling any package that
+/// more than one binary depends on to a single, strongest version constraint (see
+/// [`merge_dep_atom`]). A binary that fails to resolve only produces a warning through `listener`;
+/// it doesn't stop the other binaries' dependencies from being collected.
+pub fn resolve_many(paths: &[&Path], listener: &dyn Listener) -> Vec<String> {
+    let mut merged: HashMap<String, String> = HashMap::new();
+    for &path in paths {
+        match resolve(path) {
+            Ok(deps) => {
+                for atom in deps {
+                    let name = split_dep_atom(&atom).0.to_owned();
+                    merge_dep_atom(&mut merged, name, atom);
+                }
+            },
+            Err(err) => listener.warning(format!("{} (no auto deps for {})", err, path.display())),
+        }
+    }
+    let mut deps: Vec<String> = merged.into_values().collect();
+    deps.sort();
+    deps
+}
+
 #[test]
 #[cfg(target_os = "linux")]
 fn resolve_test() {
@@ -60,3 +128,12 @@ fn resolve_test() {
     assert!(deps.iter().any(|d| d.starts_with("libc")));
     assert!(!deps.iter().any(|d| d.starts_with("libgcc")));
 }
+
+#[test]
+fn merge_dep_atom_keeps_strongest_constraint() {
+    let mut merged = HashMap::new();
+    merge_dep_atom(&mut merged, "libc6".to_owned(), "libc6 (>= 2.27)".to_owned());
+    merge_dep_atom(&mut merged, "libc6".to_owned(), "libc6 (>= 2.31)".to_owned());
+    merge_dep_atom(&mut merged, "libc6".to_owned(), "libc6 (>= 2.2)".to_owned());
+    assert_eq!(merged.get("libc6").map(String::as_str), Some("libc6 (>= 2.31)"));
+}
@@ -0,0 +1,153 @@
+/// This module is a partial implementation of the Debian DebHelper command for installing SysV
+/// `/etc/init.d` scripts as part of a .deb package install, aka dh_installinit. It exists to
+/// complement dh_installsystemd.rs: some packages still ship a legacy init script alongside a
+/// systemd unit of the same base name for use on non-systemd hosts, and upstream dh_installinit
+/// coordinates with dh_installsystemd so the two don't double-handle the service. Specifically
+/// this implementation is based on the Ubuntu version labelled 12.10ubuntu1 which is included in
+/// Ubuntu 20.04 LTS, matching dh_installsystemd.rs's baseline.
+///
+/// # See also
+///
+/// Ubuntu 20.04 dh_installinit sources:
+/// https://git.launchpad.net/ubuntu/+source/debhelper/tree/dh_installinit?h=applied/12.10ubuntu1
+
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::manifest::Asset;
+use crate::dh_installsystemd::{InstallRecipe, PackageUnitFiles, LIB_SYSTEMD_SYSTEM_DIR};
+use crate::dh_lib::*;
+use crate::listener::Listener;
+use crate::util::*;
+use crate::CDResult;
+
+/// From man 1 dh_installinit on Ubuntu 20.04 LTS.
+/// FILES
+///        debian/package.init
+///            If this exists, it is installed into etc/init.d/ in the package
+///            build directory.
+///        debian/package.default
+///            If this exists, it is installed into etc/default/ in the
+///            package build directory.
+const ETC_INIT_D_DIR: &str = "etc/init.d/";
+const ETC_DEFAULT_DIR: &str = "etc/default/";
+
+/// See `dh_installsystemd::Options` for the systemd equivalent of these switches.
+#[derive(Default, Debug)]
+pub struct Options {
+    pub no_start: bool,
+    pub restart_after_upgrade: bool,
+}
+
+impl From<&crate::manifest::SystemdUnitsConfig> for Options {
+    fn from(config: &crate::manifest::SystemdUnitsConfig) -> Self {
+        Options {
+            no_start: !config.start,
+            restart_after_upgrade: config.restart_after_upgrade,
+        }
+    }
+}
+
+/// Find installable SysV init scripts for the specified debian package (and optional unit name)
+/// in the given directory, mirroring `dh_installsystemd::find_units`.
+pub fn find_init_scripts(
+        dir: &Path,
+        main_package: &str,
+        unit_name: Option<&str>)
+    -> PackageUnitFiles
+{
+    let mut installables = HashMap::new();
+
+    for (filename, install_dir, mode) in [("init", ETC_INIT_D_DIR, 0o755u32), ("default", ETC_DEFAULT_DIR, 0o644u32)] {
+        if let Some(src_path) = pkgfile(dir, main_package, filename, unit_name) {
+            let install_filename = unit_name.unwrap_or(main_package).to_owned();
+            let install_path = Path::new(install_dir).join(install_filename);
+            installables.insert(src_path, InstallRecipe { path: install_path, mode });
+        }
+    }
+
+    installables
+}
+
+/// Generates `postinst`/`prerm`/`postrm` maintainer script fragments for any installed
+/// `etc/init.d/` scripts.
+///
+/// When a systemd unit of the same base name is also installed (detected from `assets`, the same
+/// list passed to `dh_installsystemd::generate`), the init script's actions are wrapped in a
+/// `[ -d /run/systemd/system ]` guard so they become a no-op on a systemd host: the systemd unit
+/// wins there, while the SysV script still drives the service correctly on a non-systemd host.
+/// These guarded fragments are emitted with `prepend = true` so they run *before* the
+/// unconditional systemd fragments dh_installsystemd::generate produces for the same script,
+/// preserving the upstream "re-order service autosnippets" ordering invariant.
+pub fn generate(
+    package: &str,
+    assets: &Vec<Asset>,
+    options: &Options,
+    listener: &mut dyn Listener) -> CDResult<ScriptFragments>
+{
+    let mut scripts = ScriptFragments::new();
+
+    let init_scripts: BTreeSet<String> = assets
+        .iter()
+        .filter(|v| v.target_path.starts_with(ETC_INIT_D_DIR))
+        .map(|v| fname_from_path(v.target_path.as_path()))
+        .collect();
+
+    let systemd_units: BTreeSet<String> = assets
+        .iter()
+        .filter(|v| v.target_path.starts_with(LIB_SYSTEMD_SYSTEM_DIR))
+        .map(|v| fname_from_path(v.target_path.as_path()))
+        .collect();
+
+    for script in &init_scripts {
+        listener.info(format!("Determining augmentations needed for sysv init script {}", script));
+
+        let has_systemd_unit = systemd_units.contains(&format!("{}.service", script));
+        let replace = map!{ "SCRIPT" => script.clone() };
+
+        // Guarded so that on a systemd host this is a no-op and the unit installed by
+        // dh_installsystemd::generate takes over; unguarded otherwise.
+        let (postinst_snippet, prerm_snippet, postrm_snippet) = if has_systemd_unit {
+            ("postinst-init-guarded", "prerm-init-guarded", "postrm-init-guarded")
+        } else {
+            ("postinst-init", "prerm-init", "postrm-init")
+        };
+
+        if !options.no_start {
+            autoscript(&mut scripts, package, "postinst", postinst_snippet, &replace, true, listener)?;
+            if !options.restart_after_upgrade {
+                // Stop the service in prerm so it's down for the duration of the upgrade, rather
+                // than leaving it running until postinst restarts it afterwards.
+                autoscript(&mut scripts, package, "prerm", prerm_snippet, &replace, true, listener)?;
+            }
+        }
+
+        autoscript(&mut scripts, package, "postrm", postrm_snippet, &replace, true, listener)?;
+    }
+
+    Ok(scripts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_init_scripts_in_empty_dir_finds_nothing() {
+        let scripts = find_init_scripts(Path::new(""), "mypkg", None);
+        assert!(scripts.is_empty());
+    }
+
+    #[test]
+    fn find_init_scripts_for_package() {
+        add_test_fs_paths(&vec![
+            "debian/mypkg.init",
+            "debian/mypkg.default",
+        ]);
+        let scripts = find_init_scripts(Path::new("debian"), "mypkg", None);
+        assert_eq!(2, scripts.len());
+        assert_eq!(Path::new("etc/init.d/mypkg"), scripts[&PathBuf::from("debian/mypkg.init")].path);
+        assert_eq!(Path::new("etc/default/mypkg"), scripts[&PathBuf::from("debian/mypkg.default")].path);
+    }
+}
@@ -19,7 +19,7 @@ use std::io::prelude::*;
 use std::path::{Path, PathBuf};
 use std::str;
 
-use crate::manifest::Asset;
+use crate::manifest::{Asset, AssetSource, SystemdUnitsConfig, UnitOverride};
 use crate::dh_lib::*;
 use crate::listener::Listener;
 use crate::util::*;
@@ -40,8 +40,12 @@ use crate::CDResult;
 ///            If this exists, it is installed into usr/lib/tmpfiles.d/ in the
 ///            package build directory. Note that the "tmpfiles.d" mechanism is
 ///            currently only used by systemd.
-const LIB_SYSTEMD_SYSTEM_DIR: &str = "lib/systemd/system/";
+pub(crate) const LIB_SYSTEMD_SYSTEM_DIR: &str = "lib/systemd/system/";
 const USR_LIB_TMPFILES_D_DIR: &str = "usr/lib/tmpfiles.d/";
+/// See https://www.freedesktop.org/software/systemd/man/sysusers.d.html. Declares the
+/// users/groups that a package's `tmpfiles.d` entries (and its own files) may depend on, so
+/// `systemd-sysusers` must run before `systemd-tmpfiles --create`.
+const USR_LIB_SYSUSERS_D_DIR: &str = "usr/lib/sysusers.d/";
 const SYSTEMD_UNIT_FILE_INSTALL_MAPPINGS: [(&str, &str, &str); 12] = [
     ("",  "mount",   LIB_SYSTEMD_SYSTEM_DIR),
     ("",  "path",    LIB_SYSTEMD_SYSTEM_DIR),
@@ -57,6 +61,31 @@ const SYSTEMD_UNIT_FILE_INSTALL_MAPPINGS: [(&str, &str, &str); 12] = [
     ("",  "tmpfile", USR_LIB_TMPFILES_D_DIR),
 ];
 
+/// Per-user (`systemctl --user`) unit install directory. Unlike `LIB_SYSTEMD_SYSTEM_DIR`, there's
+/// no system-wide tmpfiles.d or mount unit equivalent at user scope.
+const USR_LIB_SYSTEMD_USER_DIR: &str = "usr/lib/systemd/user/";
+const SYSTEMD_UNIT_FILE_INSTALL_MAPPINGS_USER: [(&str, &str, &str); 8] = [
+    ("",  "path",    USR_LIB_SYSTEMD_USER_DIR),
+    ("@", "path",    USR_LIB_SYSTEMD_USER_DIR),
+    ("",  "service", USR_LIB_SYSTEMD_USER_DIR),
+    ("@", "service", USR_LIB_SYSTEMD_USER_DIR),
+    ("",  "socket",  USR_LIB_SYSTEMD_USER_DIR),
+    ("@", "socket",  USR_LIB_SYSTEMD_USER_DIR),
+    ("",  "timer",   USR_LIB_SYSTEMD_USER_DIR),
+    ("@", "timer",   USR_LIB_SYSTEMD_USER_DIR),
+];
+
+/// Whether a systemd unit is managed by the system instance (`systemctl`, started at boot/install
+/// time by root) or the per-user instance (`systemctl --user`, which cannot be started for an
+/// arbitrary user during package install). The unit's own install directory determines its scope:
+/// a unit asset targeting [`USR_LIB_SYSTEMD_USER_DIR`] is `User`, one targeting
+/// [`LIB_SYSTEMD_SYSTEM_DIR`] is `System`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitScope {
+    System,
+    User,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct InstallRecipe {
     pub path: PathBuf,
@@ -124,6 +153,50 @@ pub struct Options {
     pub no_start: bool,
     pub restart_after_upgrade: bool,
     pub no_stop_on_upgrade: bool,
+    /// Unit (base) names to skip maintainer-script generation for. Corresponds to `-X`.
+    pub exclude: Vec<String>,
+    /// When set, only these unit (base) names get maintainer-script generation.
+    pub only_units: Option<Vec<String>>,
+    /// debhelper compat level to emulate. At 13+, tmpfiles handling delegates to
+    /// `systemd-tmpfiles --create` (dh_installtmpfiles-style) instead of the older inline
+    /// `postinst-init-tmpfiles` snippet, and `preinst` fragments are generated to unmask
+    /// previously-masked units.
+    pub compat: u32,
+    /// Suppress the informational warning emitted for static (no `[Install]` section) units.
+    pub no_static_unit_warnings: bool,
+    /// Per-unit overrides of `no_enable`, keyed by unit (base) name.
+    pub unit_overrides: HashMap<String, UnitOverride>,
+    /// Don't emit the `postinst systemd-tmpfiles --create` call for installed tmpfiles.d confs.
+    pub no_tmpfiles: bool,
+    /// Don't emit the `postinst systemd-sysusers` call for installed sysusers.d confs.
+    pub no_sysusers: bool,
+}
+
+impl Options {
+    /// Whether `unit` should *not* be enabled, applying its override (if any) on top of `no_enable`.
+    fn no_enable_for(&self, unit: &str) -> bool {
+        self.unit_overrides.get(unit)
+            .and_then(|o| o.enable)
+            .map_or(self.no_enable, |enable| !enable)
+    }
+}
+
+impl From<&SystemdUnitsConfig> for Options {
+    fn from(config: &SystemdUnitsConfig) -> Self {
+        Options {
+            no_enable: !config.enable,
+            no_start: !config.start,
+            restart_after_upgrade: config.restart_after_upgrade,
+            no_stop_on_upgrade: config.no_stop_on_upgrade,
+            exclude: config.exclude.clone(),
+            only_units: config.only_units.clone(),
+            compat: config.compat,
+            no_static_unit_warnings: config.no_static_unit_warnings,
+            unit_overrides: config.unit_overrides.clone(),
+            no_tmpfiles: config.no_tmpfiles,
+            no_sysusers: config.no_sysusers,
+        }
+    }
 }
 
 /// Find installable systemd unit files for the specified debian package (and
@@ -138,12 +211,18 @@ pub struct Options {
 pub fn find_units(
         dir: &Path,
         main_package: &str,
-        unit_name: Option<&str>)
+        unit_name: Option<&str>,
+        scope: UnitScope)
     -> PackageUnitFiles
 {
     let mut installables = HashMap::new();
 
-    for (package_suffix, unit_type, install_dir) in SYSTEMD_UNIT_FILE_INSTALL_MAPPINGS.iter() {
+    let mappings: &[(&str, &str, &str)] = match scope {
+        UnitScope::System => &SYSTEMD_UNIT_FILE_INSTALL_MAPPINGS,
+        UnitScope::User => &SYSTEMD_UNIT_FILE_INSTALL_MAPPINGS_USER,
+    };
+
+    for (package_suffix, unit_type, install_dir) in mappings.iter() {
         let package = &format!("{}{}", main_package, package_suffix);
         if let Some(src_path) = pkgfile(dir, main_package, package, unit_type, unit_name) {
             // .tmpfile files should be installed in a different directory and
@@ -236,37 +315,78 @@ pub fn generate(
 {
     let mut scripts = ScriptFragments::new();
 
+    // add a postinst code block to create any users/groups declared by sysusers.d confs. Must run
+    // before the tmpfiles.d handling below, since a tmpfiles entry may specify one of these users
+    // or groups as the owner of a path it creates.
+    // see: https://www.freedesktop.org/software/systemd/man/sysusers.d.html
+    if !options.no_sysusers {
+        let sysusers_file_names = assets
+            .iter()
+            .filter(|v| v.target_path.starts_with(USR_LIB_SYSUSERS_D_DIR))
+            .map(|v | fname_from_path(v.source.path().unwrap()))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        if !sysusers_file_names.is_empty() {
+            autoscript(&mut scripts, package, "postinst", "postinst-sysusers",
+                &map!{ "SYSUSERS" => sysusers_file_names }, false, listener)?;
+        }
+    }
+
     // add postinst code blocks to handle tmpfiles
     // see: https://salsa.debian.org/debian/debhelper/-/blob/master/dh_installsystemd#L305
-    let tmp_file_names = assets
-        .iter()
-        .filter(|v| v.target_path.starts_with(USR_LIB_TMPFILES_D_DIR))
-        .map(|v | fname_from_path(v.source.path().unwrap()))
-        .collect::<Vec<String>>()
-        .join(" ");
-
-    if !tmp_file_names.is_empty() {
-        autoscript(&mut scripts, package, "postinst", "postinst-init-tmpfiles",
-            &map!{ "TMPFILES" => tmp_file_names }, false, listener)?;
+    //
+    // In compat 13+, dh_installsystemd no longer handles tmpfiles itself; that's delegated to
+    // dh_installtmpfiles, which invokes `systemd-tmpfiles --create` instead of the older inline
+    // snippet. We don't have a separate dh_installtmpfiles module, so emulate its postinst
+    // behaviour here based on the configured compat level.
+    if !options.no_tmpfiles {
+        let tmp_file_names = assets
+            .iter()
+            .filter(|v| v.target_path.starts_with(USR_LIB_TMPFILES_D_DIR))
+            .map(|v | fname_from_path(v.source.path().unwrap()))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        if !tmp_file_names.is_empty() {
+            if options.compat >= 13 {
+                autoscript(&mut scripts, package, "postinst", "postinst-tmpfiles",
+                    &map!{ "TMPFILES" => tmp_file_names }, false, listener)?;
+            } else {
+                autoscript(&mut scripts, package, "postinst", "postinst-init-tmpfiles",
+                    &map!{ "TMPFILES" => tmp_file_names }, false, listener)?;
+            }
+        }
     }
 
     // add postinst, prerm, and postrm code blocks to handle activation,
     // deactivation, start and stopping of services when the package is
     // installed, upgraded or removed.
     // see: https://git.launchpad.net/ubuntu/+source/debhelper/tree/dh_installsystemd?h=applied/12.10ubuntu1#n312
+    //
+    // Note that this is driven purely by the unit's file name, not its type, so service,
+    // socket, mount, path, target and timer units installed to LIB_SYSTEMD_SYSTEM_DIR are all
+    // covered by the same enable/start (and, on removal, stop/disable/mask) handling below,
+    // matching real dh_installsystemd's behaviour.
 
     // skip template service files. Enabling, disabling, starting or stopping
     // those services without specifying the instance is not useful.
+    // Note: units excluded here (via `-X`, or by not being named in an explicit allow-list) are
+    // still installed by `find_units()` into the package's data archive; they simply get no
+    // enable/start/stop/disable maintainer-script fragments, matching upstream dh_installsystemd.
     let mut installed_non_template_units: BTreeSet<String> = BTreeSet::new();
     installed_non_template_units.extend(assets
         .iter()
         .filter(|v| v.target_path.starts_with(LIB_SYSTEMD_SYSTEM_DIR))
         .map(|v | fname_from_path(v.target_path.as_path()))
-        .filter(|fname| !fname.contains("@")));
+        .filter(|fname| !fname.contains("@"))
+        .filter(|fname| !options.exclude.iter().any(|excluded| excluded == fname))
+        .filter(|fname| options.only_units.as_ref().map_or(true, |only| only.iter().any(|unit| unit == fname))));
 
     let mut aliases = BTreeSet::new();
     let mut enable_units = BTreeSet::new();
     let mut start_units = BTreeSet::new();
+    let mut dbus_activated_units = BTreeSet::new();
     let mut seen = BTreeSet::new();
 
     // note: we do not support handling of services with a sysv-equivalent
@@ -319,23 +439,34 @@ pub fn generate(
                     .map(|s| s.trim())
                     .next_tuple();
                 if let Some((key, value)) = possible_kv_pair {
-                    let other_unit = unquote(value).to_string();
+                    // `Also=`/`Alias=` may each list several space-separated unit names on one
+                    // line, and either key may appear more than once in the same section, so we
+                    // must tokenize the (unquoted) value rather than treat it as a single name.
+                    let other_units = unquote(value).split_ascii_whitespace().map(str::to_string);
                     match &key[..] {
                         "Also" => {
-                            // The seen lookup prevents us from looping forever over
-                            // unit files that refer to each other. An actual
-                            // real-world example of such a loop is systemd's
-                            // systemd-readahead-drop.service, which contains
-                            // Also=systemd-readahead-collect.service, and that file
-                            // in turn contains Also=systemd-readahead-drop.service,
-                            // thus forming an endless loop.
-                            // see: https://git.launchpad.net/ubuntu/+source/debhelper/tree/dh_installsystemd?h=applied/12.10ubuntu1#n340
-                            if seen.insert(other_unit.clone()) {
-                                also_units.insert(other_unit);
+                            for other_unit in other_units {
+                                // The seen lookup prevents us from looping forever over
+                                // unit files that refer to each other. An actual
+                                // real-world example of such a loop is systemd's
+                                // systemd-readahead-drop.service, which contains
+                                // Also=systemd-readahead-collect.service, and that file
+                                // in turn contains Also=systemd-readahead-drop.service,
+                                // thus forming an endless loop.
+                                // see: https://git.launchpad.net/ubuntu/+source/debhelper/tree/dh_installsystemd?h=applied/12.10ubuntu1#n340
+                                if seen.insert(other_unit.clone()) {
+                                    also_units.insert(other_unit);
+                                }
                             }
                         },
                         "Alias" => {
-                            aliases.insert(other_unit);
+                            aliases.extend(other_units);
+                        },
+                        "BusName" => {
+                            // D-Bus-activated services (the canonical example being colord's
+                            // colord.service) are deliberately static: they're started on demand
+                            // by the D-Bus daemon rather than enabled/started like a regular unit.
+                            dbus_activated_units.insert(unit.clone());
                         },
                         _ => ()
                     };
@@ -347,20 +478,56 @@ pub fn generate(
         units = also_units;
     }
 
+    // An accompanying `.busname` file (the pre-systemd-232 way of declaring D-Bus activation) also
+    // marks its matching `.service` unit as D-Bus activated, even without a `BusName=` key.
+    dbus_activated_units.extend(assets
+        .iter()
+        .filter(|v| v.target_path.starts_with(LIB_SYSTEMD_SYSTEM_DIR))
+        .map(|v| fname_from_path(v.target_path.as_path()))
+        .filter_map(|fname| fname.strip_suffix(".busname").map(|base| format!("{}.service", base))));
+
+    // A unit with no [Install] section is "static" (per dh_systemd_enable's terminology): it has
+    // no install info for systemctl to act on, so it cannot be enabled, only started. This is
+    // often deliberate (e.g. a D-Bus-activated service with no [Install] section at all), but it
+    // can also be a sign that the unit file is missing an [Install] section by mistake, so warn
+    // unless the package has explicitly opted out.
+    if !options.no_static_unit_warnings {
+        for unit in start_units.difference(&enable_units) {
+            if dbus_activated_units.contains(unit) {
+                listener.info(format!(
+                    "Systemd unit '{}' has no [Install] section and will not be enabled; it is D-Bus activated, so this is expected", unit));
+            } else {
+                listener.warning(format!(
+                    "Systemd unit '{}' has no [Install] section and will not be enabled; it will still be started. \
+                     If this is a static/D-Bus-activated unit this is expected, otherwise add an [Install] section \
+                     (or set systemd-units.no-static-unit-warnings to silence this warning)", unit));
+            }
+        }
+    }
+
     // update the maintainer scripts to enable units unless forbidden by the
     // options passed to us.
     // see: https://git.launchpad.net/ubuntu/+source/debhelper/tree/dh_installsystemd?h=applied/12.10ubuntu1#n390
     if !enable_units.is_empty() {
-        let snippet = match options.no_enable {
-            true  => "postinst-systemd-dont-enable",
-            false => "postinst-systemd-enable",
-        };
         for unit in &enable_units {
+            let snippet = match options.no_enable_for(unit) {
+                true  => "postinst-systemd-dont-enable",
+                false => "postinst-systemd-enable",
+            };
             autoscript(&mut scripts, package, "postinst", snippet,
                 &map!{ "UNITFILE" => unit.clone() }, true, listener)?;
         }
         autoscript(&mut scripts, package, "postrm", "postrm-systemd",
             &map!{ "UNITFILES" => enable_units.join(" ") }, false, listener)?;
+
+        // Compat 13+ adds a preinst fragment that unmasks a unit before install/upgrade, in case
+        // an earlier version of the package (or an admin) masked it; without this, an enable in
+        // postinst silently no-ops against a masked unit.
+        // see: https://git.launchpad.net/ubuntu/+source/debhelper/tree/dh_installsystemd?h=applied/12.10ubuntu1#n288
+        if options.compat >= 13 {
+            autoscript(&mut scripts, package, "preinst", "preinst-common",
+                &map!{ "UNITFILES" => enable_units.join(" ") }, true, listener)?;
+        }
     }
 
     // update the maintainer scripts to start units, where the exact action to
@@ -404,12 +571,59 @@ pub fn generate(
 		autoscript(&mut scripts, package, "postrm", "postrm-systemd-reload-only", &replace, false, listener)?;
     }
 
+    // Handle any units installed into USR_LIB_SYSTEMD_USER_DIR the same way as above, except that
+    // we never emit start/stop/restart fragments: a user unit runs under `systemctl --user` inside
+    // a logged-in user's session, which does not exist yet (and may never exist) at the point
+    // postinst/postrm run, so only enabling/disabling the unit for whichever users later log in is
+    // meaningful at package install/removal time.
+    let mut installed_non_template_user_units: BTreeSet<String> = BTreeSet::new();
+    installed_non_template_user_units.extend(assets
+        .iter()
+        .filter(|v| v.target_path.starts_with(USR_LIB_SYSTEMD_USER_DIR))
+        .map(|v | fname_from_path(v.target_path.as_path()))
+        .filter(|fname| !fname.contains("@"))
+        .filter(|fname| !options.exclude.iter().any(|excluded| excluded == fname))
+        .filter(|fname| options.only_units.as_ref().map_or(true, |only| only.iter().any(|unit| unit == fname))));
+
+    let mut enable_user_units = BTreeSet::new();
+    for unit in &installed_non_template_user_units {
+        listener.info(format!("Determining augmentations needed for user systemd unit {}", unit));
+
+        let needle = Path::new(USR_LIB_SYSTEMD_USER_DIR).join(unit);
+        let data = assets.iter()
+            .find(|&item| item.target_path == needle)
+            .unwrap()
+            .source
+            .data()?;
+        let reader = data.into_owned();
+
+        for line in reader.lines().map(|line| line.unwrap()).filter(|s| !is_comment(s)) {
+            if line.starts_with("[Install]") {
+                enable_user_units.insert(unit.clone());
+            }
+        }
+    }
+
+    if !enable_user_units.is_empty() {
+        let snippet = match options.no_enable {
+            true  => "postinst-systemd-user-dont-enable",
+            false => "postinst-systemd-user-enable",
+        };
+        for unit in &enable_user_units {
+            autoscript(&mut scripts, package, "postinst", snippet,
+                &map!{ "UNITFILE" => unit.clone() }, true, listener)?;
+        }
+        autoscript(&mut scripts, package, "postrm", "postrm-systemd-user",
+            &map!{ "UNITFILES" => enable_user_units.join(" ") }, false, listener)?;
+    }
+
     Ok(scripts)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::listener::NoOpListener;
 
     #[test]
     fn is_comment_detects_comments() {
@@ -479,7 +693,7 @@ mod tests {
 
     #[test]
     fn find_units_in_empty_dir_finds_nothing() {
-        let pkg_unit_files = find_units(Path::new(""), "mypkg", None);
+        let pkg_unit_files = find_units(Path::new(""), "mypkg", None, UnitScope::System);
         assert!(pkg_unit_files.is_empty());
     }
 
@@ -507,7 +721,7 @@ mod tests {
             "debian/mypkg.tmpfile",
             "debian/mypkg.myunit.service", // demonstrates lack of unit name
         ]);
-        let pkg_unit_files = find_units(Path::new("debian"), "mypkg", None);
+        let pkg_unit_files = find_units(Path::new("debian"), "mypkg", None, UnitScope::System);
         assert_eq_found_unit(&pkg_unit_files, "lib/systemd/system/mypkg.mount",   "debian/mypkg.mount");
         assert_eq_found_unit(&pkg_unit_files, "lib/systemd/system/mypkg@.path",   "debian/mypkg@.path");
         assert_eq_found_unit(&pkg_unit_files, "lib/systemd/system/mypkg.service", "debian/service");
@@ -546,7 +760,7 @@ mod tests {
             "mypkg.myunit.postinit"
         ]);
 
-        let pkg_unit_files = find_units(Path::new("debian"), "mypkg", Some("myunit"));
+        let pkg_unit_files = find_units(Path::new("debian"), "mypkg", Some("myunit"), UnitScope::System);
         // note the "myunit" target names, even when the match was less specific
         assert_eq_found_unit(&pkg_unit_files, "lib/systemd/system/myunit.mount",   "debian/mypkg.myunit.mount");
         assert_eq_found_unit(&pkg_unit_files, "lib/systemd/system/myunit@.path",   "debian/mypkg@.myunit.path");
@@ -560,4 +774,53 @@ mod tests {
 
         assert_eq!(7, pkg_unit_files.len());
     }
+
+    #[test]
+    fn find_units_for_package_user_scope() {
+        add_test_fs_paths(&vec![
+            "debian/mypkg.service",
+            "debian/mypkg@.socket",
+            "debian/mypkg.mount", // not a valid user-scope unit type, should not be matched
+            "debian/mypkg.tmpfile", // not a valid user-scope unit type, should not be matched
+        ]);
+        let pkg_unit_files = find_units(Path::new("debian"), "mypkg", None, UnitScope::User);
+        assert_eq_found_unit(&pkg_unit_files, "usr/lib/systemd/user/mypkg.service", "debian/mypkg.service");
+        assert_eq_found_unit(&pkg_unit_files, "usr/lib/systemd/user/mypkg@.socket", "debian/mypkg@.socket");
+        assert_eq!(2, pkg_unit_files.len());
+    }
+
+    #[test]
+    fn generate_splits_multi_valued_also_and_repeated_alias_lines() {
+        let assets = vec![
+            Asset::new(
+                AssetSource::Data(b"[Unit]\nDescription=main\n\n[Install]\nAlias=mypkg-a.service\nAlias=mypkg-b.service\nAlso=mypkg-helper.socket mypkg-helper.timer\n".to_vec()),
+                PathBuf::from("lib/systemd/system/mypkg.service"),
+                0o644,
+                false,
+            ),
+            Asset::new(
+                AssetSource::Data(b"[Unit]\nDescription=helper socket\n".to_vec()),
+                PathBuf::from("lib/systemd/system/mypkg-helper.socket"),
+                0o644,
+                false,
+            ),
+            Asset::new(
+                AssetSource::Data(b"[Unit]\nDescription=helper timer\n".to_vec()),
+                PathBuf::from("lib/systemd/system/mypkg-helper.timer"),
+                0o644,
+                false,
+            ),
+        ];
+        let options = Options::default();
+        let mut listener = NoOpListener;
+        let scripts = generate("mypkg", &assets, &options, &mut listener).unwrap();
+
+        // All three units (the one with [Install], plus the two pulled in via the multi-valued
+        // Also= line) must be started, proving the line was tokenized rather than treated as one
+        // bogus unit name.
+        let postinst = String::from_utf8(scripts["postinst"].clone()).unwrap();
+        assert!(postinst.contains("mypkg-helper.socket"));
+        assert!(postinst.contains("mypkg-helper.timer"));
+        assert!(postinst.contains("mypkg.service"));
+    }
 }
\ No newline at end of file
@@ -22,6 +22,7 @@ use std::path::{Path, PathBuf};
 
 use crate::{CDResult, listener::Listener};
 use crate::error::*;
+use crate::fingerprint::{FingerprintBuilder, Freshness};
 
 /// DebHelper autoscripts are embedded in the Rust library binary.
 /// The autoscripts were taken from:
@@ -126,25 +127,62 @@ fn get_embedded_autoscript(snippet_filename: &str) -> String {
     snippet
 }
 
+/// Marks the start of a block of text previously inserted by `autoscript()`.
+const AUTOSCRIPT_BEGIN_MARKER: &str = "# Automatically added by";
+/// Marks the end of a block of text previously inserted by `autoscript()`.
+const AUTOSCRIPT_END_MARKER: &str = "# End automatically added section";
+
+/// Splits a maintainer script fragment produced by (possibly repeated calls to) `autoscript()`
+/// back into the substituted body of each `AUTOSCRIPT_BEGIN_MARKER`/`AUTOSCRIPT_END_MARKER`
+/// delimited block it contains, so that callers can check whether a given block is already
+/// present before inserting it again.
+fn autoscript_blocks(text: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut lines = text.lines();
+
+    while lines.by_ref().any(|line| line.starts_with(AUTOSCRIPT_BEGIN_MARKER)) {
+        let mut body = String::new();
+        for line in lines.by_ref() {
+            if line == AUTOSCRIPT_END_MARKER {
+                break;
+            }
+            body.push_str(line);
+            body.push('\n');
+        }
+        blocks.push(body);
+    }
+
+    blocks
+}
+
 /// Build up one or more shell script fragments for a given maintainer script
 /// for a debian package in preparation for writing them into or as complete
 /// maintainer scripts in `apply()`, pulling fragments from a "library" of
 /// so-called "autoscripts".
-/// 
+///
 /// Takes a map of values to search and replace in the selected "autoscript"
-/// fragment such as a systemd unit name placeholder and value.
-/// 
+/// fragment such as a systemd unit name placeholder and value, and whether the
+/// resulting block should be prepended or appended to any fragment already
+/// collected for this `package`/`script` pair.
+///
 /// # Cargo Deb specific behaviour
-/// 
+///
 /// The autoscripts are sourced from within the binary via the rust_embed crate.
-/// 
+///
 /// Results are stored as updated or new entries in the `ScriptFragments` map,
 /// rather than being written to temporary files on disk.
-/// 
+///
+/// Before inserting a block, the existing fragment (if any) is scanned for
+/// blocks delimited by the `# Automatically added by ...`/`# End automatically
+/// added section` markers. If a block with identical (already substituted)
+/// contents is already present, the insertion is skipped so that calling this
+/// function twice with the same arguments is a no-op, rather than the
+/// maintainer script accreting duplicate blocks.
+///
 /// # Known limitations
-/// 
+///
 /// Arbitrary sed command based file editing is not supported.
-/// 
+///
 /// # References
 ///
 /// https://git.launchpad.net/ubuntu/+source/debhelper/tree/lib/Debian/Debhelper/Dh_Lib.pm?h=applied/12.10ubuntu1#n1135
@@ -154,41 +192,44 @@ pub(crate) fn autoscript(
     script: &str,
     snippet_filename: &str,
     replacements: &HashMap<&str, String>,
+    prepend: bool,
     listener: &mut dyn Listener) -> CDResult<()>
 {
+    if replacements.is_empty() {
+        // We don't support sed commands yet.
+        unimplemented!();
+    }
+
     let bin_name = std::env::current_exe().unwrap();
     let bin_name = bin_name.file_name().unwrap();
     let bin_name = bin_name.to_str().unwrap();
     let outfile = format!("{}.{}.debhelper", package, script);
 
+    let existing_text = match scripts.get(&outfile) {
+        Some(bytes) => std::str::from_utf8(bytes)?.to_owned(),
+        None => String::new(),
+    };
+
+    let body = autoscript_sed(snippet_filename, replacements);
+    if autoscript_blocks(&existing_text).iter().any(|existing_body| existing_body == &body) {
+        listener.info(format!("Maintainer script {} already has autoscript {} applied, skipping", &script, snippet_filename));
+        return Ok(());
+    }
+
     listener.info(format!("Maintainer script {} will be augmented with autoscript {}", &script, snippet_filename));
 
-    if scripts.contains_key(&outfile) && (script == "postrm" || script == "prerm") {
-        if !replacements.is_empty() {
-            let existing_text = std::str::from_utf8(scripts.get(&outfile).unwrap())?;
-
-            // prepend new text to existing script fragment
-            let mut new_text = String::new();
-            new_text.push_str(&format!("# Automatically added by {}\n", bin_name));
-            new_text.push_str(&autoscript_sed(snippet_filename, replacements));
-            new_text.push_str("# End automatically added section\n");
-            new_text.push_str(existing_text);
-            scripts.insert(outfile, new_text.into());
-        } else {
-            // We don't support sed commands yet.
-            unimplemented!();
-        }
-    } else if !replacements.is_empty() {
-        // append to existing script fragment (if any)
-        let mut new_text = String::from(std::str::from_utf8(scripts.get(&outfile).unwrap_or(&Vec::new()))?);
-        new_text.push_str(&format!("# Automatically added by {}\n", bin_name));
-        new_text.push_str(&autoscript_sed(snippet_filename, replacements));
-        new_text.push_str("# End automatically added section\n");
-        scripts.insert(outfile, new_text.into());
+    let mut block = String::new();
+    block.push_str(&format!("{} {}\n", AUTOSCRIPT_BEGIN_MARKER, bin_name));
+    block.push_str(&body);
+    block.push_str(AUTOSCRIPT_END_MARKER);
+    block.push('\n');
+
+    let new_text = if prepend {
+        format!("{}{}", block, existing_text)
     } else {
-        // We don't support sed commands yet.
-        unimplemented!();
-    }
+        format!("{}{}", existing_text, block)
+    };
+    scripts.insert(outfile, new_text.into());
 
     Ok(())
 }
@@ -209,73 +250,140 @@ fn autoscript_sed(snippet_filename: &str, replacements: &HashMap<&str, String>)
     snippet
 }
 
+/// Marker line written at the top of a maintainer script that cargo-deb generated wholesale
+/// (i.e. no `#DEBHELPER#` token was substituted into user-authored content). Mirrors the
+/// `@generated` convention Cargo itself uses for `Cargo.lock`: it tells code-review tooling the
+/// file can be diff-collapsed, and tells a later `cargo deb` run that the *whole* file is safe to
+/// regenerate from scratch, because none of it was hand written.
+const GENERATED_MARKER: &str = "This file is @generated by cargo-deb. Do not edit it directly; it will be overwritten.";
+
+/// True if `text` carries the [`GENERATED_MARKER`] near its top, i.e. it is the unmodified output
+/// of a previous wholesale-generation pass rather than a user-authored (or partly user-authored)
+/// script.
+fn is_generated(text: &str) -> bool {
+    text.lines().take(4).any(|line| line.contains(GENERATED_MARKER))
+}
+
+/// Builds a complete maintainer script out of `generated_text` (the merged autoscript fragments
+/// for this script), with a shebang header and an `@generated` marker so it's recognisable as
+/// wholly machine-written.
+fn wholesale_generated_script(generated_text: &str) -> String {
+    let mut new_text = String::new();
+    new_text.push_str("#!/bin/sh\n");
+    new_text.push_str(&format!("# {}\n", GENERATED_MARKER));
+    new_text.push_str("set -e\n");
+    new_text.push_str(generated_text);
+    new_text
+}
+
 /// Copy the merged autoscript fragments to the final maintainer script, either
 /// at the point where the user placed a #DEBHELPER# token to indicate where
 /// they should be inserted, or by adding a shebang header to make the fragments
 /// into a complete shell script.
 ///
 /// # Cargo Deb specific behaviour
-/// 
+///
 /// Results are stored as updated or new entries in the `ScriptFragments` map,
 /// rather than being written to temporary files on disk.
-/// 
+///
+/// If the on-disk user file has no `#DEBHELPER#` token to substitute into, but does carry the
+/// [`GENERATED_MARKER`] left by an earlier wholesale-generation pass, it is treated the same as
+/// the "no user file" case below and regenerated in full: it's known to be our own previous
+/// output rather than something user-authored, so there's nothing to preserve. Otherwise a
+/// missing token is an error, to avoid silently producing a script that never runs the generated
+/// fragments.
+///
 /// # Known limitations
-/// 
+///
 /// We only replace #DEBHELPER#. Is that enough? See:
 ///   https://www.man7.org/linux/man-pages/man1/dh_installdeb.1.html#SUBSTITUTION_IN_MAINTAINER_SCRIPTS
 ///
 /// # References
 ///
 /// https://git.launchpad.net/ubuntu/+source/debhelper/tree/lib/Debian/Debhelper/Dh_Lib.pm?h=applied/12.10ubuntu1#n2161
-fn debhelper_script_subst(user_scripts_dir: &Path, scripts: &mut ScriptFragments, package: &str, script: &str, unit_name: Option<&str>,
+fn debhelper_script_subst(
+    user_scripts_dir: &Path,
+    fingerprint_dir: &Path,
+    extra_rerun_if_changed: &[PathBuf],
+    scripts: &mut ScriptFragments,
+    package: &str,
+    script: &str,
+    unit_name: Option<&str>,
     listener: &mut dyn Listener) -> CDResult<()>
 {
     let user_file = pkgfile(user_scripts_dir, package, script, unit_name);
     let generated_file_name = format!("{}.{}.debhelper", package, script);
 
+    // merge the generated scripts if they exist into the user script
+    // if no generated script exists, we still need to remove #DEBHELPER# if
+    // present otherwise the script will be syntactically invalid
+    let generated_text = match scripts.get(&generated_file_name) {
+        Some(contents) => String::from_utf8(contents.clone())?,
+        None           => String::from("")
+    };
+
+    // Fingerprint the inputs that determine this script's content: the merged autoscript
+    // template/substitutions, the user-supplied fragment (if any) and any extra files the user
+    // declared via `maintainer-scripts-rerun-if-changed`. Unchanged inputs since the last run are
+    // reported as fresh; this doesn't skip producing the content (still needed for this run's
+    // archive) but avoids spamming progress output for work that didn't actually change anything.
+    let mut fingerprint = FingerprintBuilder::new();
+    fingerprint.add("generated", &generated_text);
+    if let Some(ref user_file_path) = user_file {
+        fingerprint.add_file(user_file_path)?;
+    }
+    for extra in extra_rerun_if_changed {
+        fingerprint.add_file(extra)?;
+    }
+    let fingerprint_path = fingerprint_dir.join(format!("{}.{}", package, script));
+    let freshness = fingerprint.finish().check_and_store(&fingerprint_path)?;
+
     if let Some(user_file_path) = user_file {
-        listener.info(format!("Augmenting maintainer script {}", user_file_path.display()));
-
-        // merge the generated scripts if they exist into the user script
-        // if no generated script exists, we still need to remove #DEBHELPER# if
-        // present otherwise the script will be syntactically invalid
-        let generated_text = match scripts.get(&generated_file_name) {
-            Some(contents) => String::from_utf8(contents.clone())?,
-            None           => String::from("")
-        };
         let user_text = std::fs::read_to_string(user_file_path.as_path())?;
-        let new_text = user_text.replace("#DEBHELPER#", &generated_text);
-        if new_text == user_text {
+
+        if user_text.contains("#DEBHELPER#") {
+            log_freshness(listener, &freshness, format_args!("Augmenting maintainer script {}", user_file_path.display()));
+            let new_text = user_text.replace("#DEBHELPER#", &generated_text);
+            scripts.insert(script.into(), new_text.into());
+        } else if is_generated(&user_text) {
+            log_freshness(listener, &freshness, format_args!("Regenerating maintainer script {}", user_file_path.display()));
+            scripts.insert(script.into(), wholesale_generated_script(&generated_text).into());
+        } else {
             return Err(CargoDebError::DebHelperReplaceFailed(user_file_path));
         }
-        scripts.insert(script.into(), new_text.into());
-    } else if let Some(generated_bytes) = scripts.get(&generated_file_name) {
-        listener.info(format!("Generating maintainer script {}", script));
-
-        // give it a shebang header and rename it
-        let mut new_text = String::new();
-        new_text.push_str("#!/bin/sh\n");
-        new_text.push_str("set -e\n");
-        new_text.push_str(std::str::from_utf8(generated_bytes)?);
-
-        scripts.insert(script.into(), new_text.into());
+    } else if scripts.contains_key(&generated_file_name) {
+        log_freshness(listener, &freshness, format_args!("Generating maintainer script {}", script));
+        scripts.insert(script.into(), wholesale_generated_script(&generated_text).into());
     }
 
     Ok(())
 }
 
+/// Logs `what` happening, noting via `freshness` whether it was actually necessary: `FRESH` when
+/// none of the tracked inputs changed since the last run, `DIRTY` (with the reason) otherwise.
+fn log_freshness(listener: &mut dyn Listener, freshness: &Freshness, what: std::fmt::Arguments) {
+    match freshness {
+        Freshness::Fresh => listener.info(format!("{} (FRESH, no inputs changed)", what)),
+        Freshness::Dirty(reason) => listener.info(format!("{} (DIRTY: {})", what, reason)),
+    }
+}
+
 /// Generate final maintainer scripts by merging the autoscripts that have been
 /// collected in the `ScriptFragments` map  with the maintainer scripts
 /// on disk supplied by the user.
-/// 
+///
+/// `fingerprint_dir` is where each generated script's fingerprint from this run is recorded, to
+/// detect on the next run whether its inputs (template, substitutions, user fragment, any extra
+/// `extra_rerun_if_changed` files) actually changed. See [`crate::fingerprint`].
+///
 /// See: https://git.launchpad.net/ubuntu/+source/debhelper/tree/dh_installdeb?h=applied/12.10ubuntu1#n300
-pub(crate) fn apply(user_scripts_dir: &Path, scripts: &mut ScriptFragments, package: &str, unit_name: Option<&str>,
+pub(crate) fn apply(user_scripts_dir: &Path, fingerprint_dir: &Path, extra_rerun_if_changed: &[PathBuf], scripts: &mut ScriptFragments, package: &str, unit_name: Option<&str>,
     listener: &mut dyn Listener) -> CDResult<()>
 {
     for script in &["postinst", "preinst", "prerm", "postrm"] {
         // note: we don't support custom defines thus we don't have the final
         // 'package_subst' argument to debhelper_script_subst().
-        debhelper_script_subst(user_scripts_dir, scripts, package, script, unit_name, listener)?;
+        debhelper_script_subst(user_scripts_dir, fingerprint_dir, extra_rerun_if_changed, scripts, package, script, unit_name, listener)?;
     }
 
     Ok(())
@@ -452,30 +560,29 @@ cfg_if! {
                 assert_eq!(None, r);
             }
 
-            fn autoscript_test_wrapper(pkg: &str, script: &str, snippet: &str, unit: &str, scripts: Option<ScriptFragments>)
+            fn autoscript_test_wrapper(pkg: &str, script: &str, snippet: &str, unit: &str, scripts: Option<ScriptFragments>, prepend: bool)
                 -> ScriptFragments
             {
                 let mut mock_listener = crate::listener::MockListener::new();
                 mock_listener.expect_info().times(1).return_const(());
                 let mut scripts = scripts.unwrap_or(ScriptFragments::new());
                 let replacements = map!{ "UNITFILES" => unit.to_owned() };
-                autoscript(&mut scripts, pkg, script, snippet, &replacements, &mut mock_listener).unwrap();
+                autoscript(&mut scripts, pkg, script, snippet, &replacements, prepend, &mut mock_listener).unwrap();
                 return scripts;
             }
 
             #[test]
             #[should_panic(expected = "Unknown autoscript 'idontexist'")]
             fn autoscript_panics_with_unknown_autoscript() {
-                autoscript_test_wrapper("mypkg", "somescript", "idontexist", "dummyunit", None);
+                autoscript_test_wrapper("mypkg", "somescript", "idontexist", "dummyunit", None, false);
             }
 
             #[test]
             #[should_panic(expected = "not implemented")]
             fn autoscript_panics_in_sed_mode() {
                 let mut mock_listener = crate::listener::MockListener::new();
-                mock_listener.expect_info().times(1).return_const(());
                 let mut scripts = ScriptFragments::new();
-                autoscript(&mut scripts, "mypkg", "somescript", "idontexist", &HashMap::new(), &mut mock_listener).unwrap();
+                autoscript(&mut scripts, "mypkg", "somescript", "idontexist", &HashMap::new(), false, &mut mock_listener).unwrap();
             }
 
             #[test]
@@ -484,14 +591,26 @@ cfg_if! {
                 actual_scripts.sort();
 
                 let expected_scripts = vec![
+                    "postinst-init",
+                    "postinst-init-guarded",
                     "postinst-init-tmpfiles",
                     "postinst-systemd-dont-enable",
                     "postinst-systemd-enable",
                     "postinst-systemd-restart",
                     "postinst-systemd-restartnostart",
                     "postinst-systemd-start",
+                    "postinst-systemd-user-dont-enable",
+                    "postinst-systemd-user-enable",
+                    "postinst-sysusers",
+                    "postinst-tmpfiles",
+                    "postrm-init",
+                    "postrm-init-guarded",
                     "postrm-systemd",
                     "postrm-systemd-reload-only",
+                    "postrm-systemd-user",
+                    "preinst-common",
+                    "prerm-init",
+                    "prerm-init-guarded",
                     "prerm-systemd",
                     "prerm-systemd-restart",
                 ];
@@ -502,7 +621,7 @@ cfg_if! {
             #[test]
             fn autoscript_sanity_check_with_embedded_snippets() {
                 for snippet_filename in Autoscripts::iter() {
-                    autoscript_test_wrapper("mypkg", "somescript", &snippet_filename, "dummyunit", None);
+                    autoscript_test_wrapper("mypkg", "somescript", &snippet_filename, "dummyunit", None, false);
                 }
             }
 
@@ -517,7 +636,7 @@ cfg_if! {
 
                 // Populate an autoscript template and add the result to a
                 // collection of scripts and return it to us.
-                let scripts = autoscript_test_wrapper("mypkg", maintainer_script, &autoscript_name, "dummyunit", None);
+                let scripts = autoscript_test_wrapper("mypkg", maintainer_script, &autoscript_name, "dummyunit", None, prepend);
 
                 // Expect autoscript() to have created one temporary script
                 // fragment called <package>.<script>.debhelper.
@@ -559,7 +678,7 @@ cfg_if! {
                 // populated but this time with the different value, and pass in
                 // the existing set of created scripts to check how it gets
                 // modified.
-                let scripts = autoscript_test_wrapper("mypkg", maintainer_script, &autoscript_name, "otherunit", Some(scripts));
+                let scripts = autoscript_test_wrapper("mypkg", maintainer_script, &autoscript_name, "otherunit", Some(scripts), prepend);
 
                 // The number and name of the output scripts should remain the same
                 assert_eq!(1, scripts.len());
@@ -592,6 +711,21 @@ cfg_if! {
                     assert_eq!(expected_autoscript_text2, created_autoscript_text2);
                 }
             }
+
+            #[rstest(prepend, case(true), case(false))]
+            fn autoscript_does_not_duplicate_an_already_applied_block(prepend: bool) {
+                // Apply the same autoscript with the same unit name twice in a row, as would
+                // happen if `cargo deb` were invoked twice against the same project.
+                let scripts = autoscript_test_wrapper("mypkg", "postinst", "postinst-systemd-enable", "dummyunit", None, prepend);
+                let scripts = autoscript_test_wrapper("mypkg", "postinst", "postinst-systemd-enable", "dummyunit", Some(scripts), prepend);
+
+                assert_eq!(1, scripts.len());
+                let (_, created_bytes) = scripts.iter().next().unwrap();
+                let created_text = std::str::from_utf8(created_bytes).unwrap();
+
+                // Only one block should have been inserted, not two.
+                assert_eq!(1, autoscript_blocks(created_text).len());
+            }
        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,52 @@
+//! `cargo deb --diff`: assembles the maintainer scripts cargo-deb would generate without
+//! packaging anything, and prints a unified diff against whatever currently exists in the
+//! package tree, so packaging changes (including injected autoscript blocks) can be reviewed,
+//! or enforced by CI, before a real build.
+
+use crate::control::generate_maintainer_scripts;
+use crate::error::*;
+use crate::listener::Listener;
+use crate::manifest::Config;
+use similar::TextDiff;
+
+/// The maintainer scripts `generate_maintainer_scripts` may produce; `config` and `templates`
+/// are plain user files copied verbatim and so have nothing to diff against.
+const MAINTAINER_SCRIPT_NAMES: &[&str] = &["preinst", "postinst", "prerm", "postrm"];
+
+/// Prints a unified diff of every maintainer script cargo-deb would generate for `options`
+/// against the corresponding file under `options.maintainer_scripts` (if any), and returns
+/// `true` if anything differed.
+pub fn diff_maintainer_scripts(options: &Config, listener: &mut dyn Listener) -> CDResult<bool> {
+    let mut scripts = generate_maintainer_scripts(options, listener)?;
+    let maintainer_scripts_dir = options.maintainer_scripts.as_deref();
+
+    let mut any_diff = false;
+    for &name in MAINTAINER_SCRIPT_NAMES {
+        let generated = match scripts.remove(&name.to_string()) {
+            Some(bytes) => bytes,
+            None => continue,
+        };
+        let generated_text = String::from_utf8_lossy(&generated);
+
+        let existing_path = maintainer_scripts_dir.map(|dir| dir.join(name));
+        let existing_text = existing_path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .unwrap_or_default();
+
+        if existing_text == generated_text {
+            continue;
+        }
+        any_diff = true;
+
+        let label_a = format!("a/{}", name);
+        let label_b = format!("b/{}", name);
+        let diff = TextDiff::from_lines(&existing_text, &generated_text[..])
+            .unified_diff()
+            .header(&label_a, &label_b)
+            .to_string();
+        print!("{}", diff);
+    }
+
+    Ok(any_diff)
+}
@@ -55,6 +55,11 @@ quick_error! {
             display("unable to parse `cargo metadata` output")
             cause(err)
         }
+        CargoMetadata(err: cargo_metadata::Error) {
+            from()
+            display("unable to run `cargo metadata`")
+            cause(err)
+        }
         PackageNotFound(path: String, reason: Vec<u8>) {
             display("path '{}' does not belong to a package: {}", path, String::from_utf8_lossy(reason))
         }
@@ -80,6 +85,30 @@ quick_error! {
             display("unable to iterate asset glob result")
             cause(err)
         }
+        LzmaCompressionError(err: xz2::stream::Error) {
+            from()
+            display("xz compression failed: {}", err)
+            cause(err)
+        }
+        ZstdCompressionError(err: io::Error) {
+            display("zstd compression failed: {}", err)
+            cause(err)
+        }
+        UnknownCompressionType(value: String) {
+            display("unknown --compress-type '{}': expected one of gzip, xz, zstd, none", value)
+        }
+        UnknownCapability(name: String) {
+            display("unknown Linux capability \"{}\"", name)
+        }
+        OutputNotWriteable(path: PathBuf) {
+            display("output path '{}' already exists and is not writeable", path.display())
+        }
+        VerificationFailed(reason: String) {
+            display("generated .deb failed verification: {}", reason)
+        }
+        DebHelperReplaceFailed(path: PathBuf) {
+            display("Unable to replace #DEBHELPER# token in maintainer script {}: token not found and file wasn't cargo-deb generated", path.display())
+        }
     }
 }
 
@@ -0,0 +1,109 @@
+//! Lightweight "has anything this generated output depends on changed since the last run"
+//! checks, mirroring Cargo's own `.fingerprint` rebuild detection.
+//!
+//! A [`Fingerprint`] combines a content hash of an arbitrary number of named inputs (template
+//! text, substitution values, user-supplied files) with the newest modification time among any
+//! file inputs, so a cheap mtime comparison can usually skip comparing against the stored
+//! (authoritative) content hash on the next run.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::error::*;
+
+/// Accumulates the inputs that determine whether a generated output needs regenerating.
+#[derive(Default)]
+pub(crate) struct FingerprintBuilder {
+    hasher: DefaultHasher,
+    newest_input_mtime: Option<SystemTime>,
+    /// Whether every input mixed in so far came through [`Self::add_file`]. The mtime shortcut
+    /// in [`Fingerprint::check_and_store`] is only sound when this stays `true`: a plain
+    /// [`Self::add`] input (e.g. a substitution value) has no mtime of its own, so its changing
+    /// wouldn't be reflected in `newest_input_mtime` at all.
+    all_inputs_are_files: bool,
+}
+
+impl FingerprintBuilder {
+    pub(crate) fn new() -> Self {
+        Self { all_inputs_are_files: true, ..Self::default() }
+    }
+
+    /// Mixes a named input (e.g. a template's contents, or a substitution value) into the
+    /// fingerprint. `key` is hashed along with `value` so that, say, an empty `UNITFILES`
+    /// substitution can't collide with an empty `UNITFILE` one.
+    pub(crate) fn add(&mut self, key: &str, value: &str) -> &mut Self {
+        key.hash(&mut self.hasher);
+        value.hash(&mut self.hasher);
+        self.all_inputs_are_files = false;
+        self
+    }
+
+    /// Mixes in a "rerun-if-changed" file: its contents (so edits invalidate the fingerprint) and
+    /// its modification time (so the cheap pre-check in [`Fingerprint::check_and_store`] can skip
+    /// the hash comparison when nothing has touched any tracked file). The file is always read and
+    /// hashed here.
+    pub(crate) fn add_file(&mut self, path: &Path) -> CDResult<&mut Self> {
+        let data = fs::read(path)
+            .map_err(|e| CargoDebError::IoFile("unable to read rerun-if-changed input", e, path.to_owned()))?;
+        "file".hash(&mut self.hasher);
+        String::from_utf8_lossy(&data).hash(&mut self.hasher);
+        if let Ok(mtime) = path.metadata().and_then(|m| m.modified()) {
+            self.newest_input_mtime = Some(self.newest_input_mtime.map_or(mtime, |newest| newest.max(mtime)));
+        }
+        Ok(self)
+    }
+
+    pub(crate) fn finish(&self) -> Fingerprint {
+        Fingerprint {
+            hash: self.hasher.finish(),
+            newest_input_mtime: self.newest_input_mtime.filter(|_| self.all_inputs_are_files),
+        }
+    }
+}
+
+/// Whether a generated output needs to be regenerated, and if so why (for `--verbose` logging).
+pub(crate) enum Freshness {
+    /// Nothing tracked by the fingerprint changed since the last run.
+    Fresh,
+    /// Something changed; carries a short human-readable reason.
+    Dirty(String),
+}
+
+/// A snapshot of a [`FingerprintBuilder`]'s accumulated inputs.
+pub(crate) struct Fingerprint {
+    hash: u64,
+    newest_input_mtime: Option<SystemTime>,
+}
+
+impl Fingerprint {
+    /// Compares this fingerprint against the one stored at `path` (if any) from a previous run,
+    /// reports the result as [`Freshness`], then persists this fingerprint to `path` for next
+    /// time.
+    pub(crate) fn check_and_store(&self, path: &Path) -> CDResult<Freshness> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Cheap pre-check: if none of the tracked inputs are newer than the fingerprint file we
+        // wrote last time, we already know nothing changed and can skip the hash comparison.
+        if let (Some(newest_input), Ok(stored_meta)) = (self.newest_input_mtime, fs::metadata(path)) {
+            if let Ok(stored_mtime) = stored_meta.modified() {
+                if newest_input <= stored_mtime {
+                    return Ok(Freshness::Fresh);
+                }
+            }
+        }
+
+        let previous_hash = fs::read_to_string(path).ok().and_then(|s| s.trim().parse::<u64>().ok());
+        fs::write(path, self.hash.to_string())?;
+
+        Ok(match previous_hash {
+            Some(hash) if hash == self.hash => Freshness::Fresh,
+            Some(_) => Freshness::Dirty("its inputs changed since the last run".to_owned()),
+            None => Freshness::Dirty("no fingerprint was recorded by a previous run".to_owned()),
+        })
+    }
+}
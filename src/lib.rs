@@ -22,30 +22,41 @@ The library interface is experimental. See `main.rs` for usage.
 pub mod compress;
 pub mod control;
 pub mod data;
+pub mod diff;
 pub mod listener;
 pub mod manifest;
+pub mod verify;
 pub use crate::debarchive::DebArchive;
 pub use crate::error::*;
 pub use crate::manifest::Config;
+pub use crate::transaction::Transaction;
 
 mod config;
 mod debarchive;
 mod dependencies;
 mod error;
+mod fingerprint;
+mod license_detect;
 mod ok_or;
 mod pathbytes;
+mod rust_target;
 mod tararchive;
+mod transaction;
 mod wordsplit;
+mod dh_installinit;
 mod dh_installsystemd;
 mod dh_lib;
 mod util;
 
+use crate::compress::Compressed;
 use crate::listener::Listener;
+use cargo_metadata::Message;
 use std::env;
 use std::fs;
-use std::io;
-use std::path::Path;
-use std::process::{Command, ExitStatus};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time;
 
 const TAR_REJECTS_CUR_DIR: bool = true;
 
@@ -83,14 +94,65 @@ pub fn reset_deb_temp_directory(options: &Config) -> io::Result<()> {
 /// Removes the target/debian/foo
 pub fn remove_deb_temp_directory(options: &Config) {
     let deb_temp_dir = options.deb_temp_dir();
-    let _ = fs::remove_dir(&deb_temp_dir);
+    let _ = remove_dir_all_writeable(&deb_temp_dir);
 }
 
-/// Builds a release binary with `cargo build --release`
-pub fn cargo_build(options: &Config, target: Option<&str>, other_flags: &[String], verbose: bool) -> CDResult<()> {
+/// Recursively removes `path`, walking depth-first and deleting files before the directories that
+/// contained them. Unlike `fs::remove_dir_all`, first makes each read-only entry writeable
+/// (`chmod +w`, roughly) before unlinking it, so assets staged with restrictive modes, or leftovers
+/// from a previous interrupted run, don't block cleanup.
+pub(crate) fn remove_dir_all_writeable(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            remove_dir_all_writeable(&entry?.path())?;
+        }
+        make_writeable(path, &metadata)?;
+        fs::remove_dir(path)
+    } else {
+        make_writeable(path, &metadata)?;
+        fs::remove_file(path)
+    }
+}
+
+/// Clears the read-only bit on `path` (a no-op if it's already writeable).
+fn make_writeable(path: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    let mut permissions = metadata.permissions();
+    if permissions.readonly() {
+        permissions.set_readonly(false);
+        fs::set_permissions(path, permissions)?;
+    }
+    Ok(())
+}
+
+/// One executable or cdylib artifact `cargo build` actually produced, as reported by its
+/// `--message-format=json` `compiler-artifact` messages. `name`/`kind` mirror the `[[bin]]`/`[lib]`
+/// target that built it (e.g. `kind == ["bin"]` or `["cdylib"]`), so callers can match it back up
+/// to the `Asset` they guessed a path for.
+#[derive(Clone)]
+pub struct BuiltArtifact {
+    pub name: String,
+    pub kind: Vec<String>,
+    pub path: PathBuf,
+}
+
+/// Builds the binary for `options.build_profile`, returning the real paths cargo reports it
+/// produced (see [`BuiltArtifact`]), rather than leaving callers to reconstruct them with
+/// [`Config::path_in_build`]'s `target/<triple>/<profile>/<name>` guess.
+pub fn cargo_build(options: &Config, target: Option<&str>, other_flags: &[String], verbose: bool) -> CDResult<Vec<BuiltArtifact>> {
     let mut cmd = Command::new("cargo");
     cmd.current_dir(&options.manifest_dir);
-    cmd.arg("build").args(&["--release", "--all"]);
+    cmd.arg("build").arg("--all").arg("--message-format=json-render-diagnostics");
+    match options.build_profile.as_str() {
+        "release" => { cmd.arg("--release"); },
+        "dev" => {}, // cargo's default; no flag needed
+        profile => { cmd.arg(format!("--profile={}", profile)); },
+    }
 
     for flag in other_flags {
         cmd.arg(flag);
@@ -118,16 +180,33 @@ pub fn cargo_build(options: &Config, target: Option<&str>, other_flags: &[String
         cmd.arg(format!("--features={}", features.join(",")));
     }
 
-    let status = cmd.status()
+    cmd.stdout(Stdio::piped());
+    let mut child = cmd.spawn().map_err(|e| CargoDebError::CommandFailed(e, "cargo"))?;
+    let stdout = child.stdout.take().expect("cargo's stdout was piped");
+
+    let mut artifacts = Vec::new();
+    for message in Message::parse_stream(BufReader::new(stdout)) {
+        let message = message.map_err(|e| CargoDebError::CommandFailed(e, "cargo"))?;
+        if let Message::CompilerArtifact(artifact) = message {
+            let path = artifact.executable
+                .or_else(|| artifact.filenames.into_iter().next())
+                .map(|p| p.into_std_path_buf());
+            if let Some(path) = path {
+                artifacts.push(BuiltArtifact { name: artifact.target.name, kind: artifact.target.kind, path });
+            }
+        }
+    }
+
+    let status = child.wait()
         .map_err(|e| CargoDebError::CommandFailed(e, "cargo"))?;
     if !status.success() {
         return Err(CargoDebError::BuildFailed);
     }
-    Ok(())
+    Ok(artifacts)
 }
 
 // Maps Rust's blah-unknown-linux-blah to Debian's blah-linux-blah
-fn debian_triple(rust_target_triple: &str) -> String {
+pub(crate) fn debian_triple(rust_target_triple: &str) -> String {
     let mut p = rust_target_triple.split('-');
     let arch = p.next().unwrap();
     let abi = p.last().unwrap_or("");
@@ -254,3 +333,183 @@ pub fn strip_binaries(options: &mut Config, target: Option<&str>, listener: &mut
 
     Ok(())
 }
+
+/// Strips build-host `DT_RPATH`/`DT_RUNPATH` entries from packaged ELF binaries via `patchelf`,
+/// so the `.deb` doesn't leak the build machine's filesystem layout and stays relocatable once
+/// installed under `/usr/bin`/`/usr/lib`. Controlled by `Config.fix_rpath`, on by default.
+pub fn fix_rpaths(options: &Config, listener: &mut dyn Listener) -> CDResult<()> {
+    for asset in options.built_binaries() {
+        let path = match asset.source.path() {
+            Some(path) => path,
+            None => continue,
+        };
+
+        let output = Command::new("patchelf")
+            .arg("--print-rpath")
+            .arg(path)
+            .output()
+            .map_err(|e| CargoDebError::CommandFailed(e, "patchelf"))?;
+        if !output.status.success() {
+            // Not every binary has a dynamic section (e.g. a static binary); nothing to fix up.
+            continue;
+        }
+        let rpath = String::from_utf8_lossy(&output.stdout);
+        let rpath = rpath.trim();
+        if rpath.is_empty() {
+            continue;
+        }
+
+        Command::new("patchelf")
+            .arg("--remove-rpath")
+            .arg(path)
+            .status()
+            .and_then(ensure_success)
+            .map_err(|e| CargoDebError::CommandFailed(e, "patchelf"))?;
+        listener.info(format!("Removed RPATH '{}' from '{}'", rpath, path.display()));
+    }
+    Ok(())
+}
+
+/// One stage of the `cargo deb` build pipeline, in the order it runs. [`run_phases`] runs every
+/// stage from `from` through `to` (inclusive) against an already-resolved [`Config`], threading
+/// the intermediate state (built artifact paths, staged/stripped assets) between them itself, so
+/// a caller only needs to say which contiguous slice it wants — e.g. stop right after stripping
+/// without assembling a `.deb`, or start at [`Phase::BuildControl`] to package an already-built,
+/// already-staged tree without re-invoking `cargo build`.
+///
+/// [`Phase::ResolveConfig`] (building the `Config` itself, via [`Config::from_manifest`]) isn't
+/// run by `run_phases` — it needs CLI-level arguments (manifest path, package name, variant, ...)
+/// `run_phases` doesn't take — but it's named here so a `from`/`to` range can still start "before
+/// everything else" for documentation purposes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    /// Resolve `Config` from `Cargo.toml` and `cargo metadata`. Always the caller's own job.
+    ResolveConfig,
+    /// Run `cargo build` for `options.build_profile`, recording the real paths of what it produced.
+    CargoBuild,
+    /// Resolve explicit/glob assets and compress documentation per Debian policy. Ordered after
+    /// `CargoBuild` (assets can't be resolved against build output that doesn't exist yet) and
+    /// before `Strip` (stripping needs the resolved, real path of each built asset).
+    GatherAssets,
+    /// Strip debug symbols from built binaries (and remove build-host RPATH/RUNPATH entries).
+    Strip,
+    /// Generate the compressed `data.tar`/`control.tar` archive members.
+    BuildControl,
+    /// Assemble the final `.deb` ar archive from those members.
+    AssembleDeb,
+}
+
+/// Runs every [`Phase`] from `from` through `to` (inclusive) against `options`, returning the
+/// `.deb`'s path once [`Phase::AssembleDeb`] has run (`None` if `to` stops earlier).
+///
+/// `target`/`other_flags`/`verbose` only matter when [`Phase::CargoBuild`] is in range;
+/// `separate_debug_symbols` only when [`Phase::Strip`] is.
+pub fn run_phases(
+    options: &mut Config,
+    from: Phase,
+    to: Phase,
+    target: Option<&str>,
+    other_flags: &[String],
+    verbose: bool,
+    separate_debug_symbols: bool,
+    listener: &mut dyn Listener,
+) -> CDResult<Option<PathBuf>> {
+    if from > to {
+        return Err(CargoDebError::Str("phase pipeline's `from` must not come after `to`"));
+    }
+
+    if from <= Phase::CargoBuild && to >= Phase::CargoBuild {
+        let built_artifacts = cargo_build(options, target, other_flags, verbose)?;
+        options.apply_build_artifacts(&built_artifacts);
+    }
+
+    if from <= Phase::GatherAssets && to >= Phase::GatherAssets {
+        options.resolve_assets()?;
+        crate::data::compress_documentation(options, listener)?;
+    }
+
+    if from <= Phase::Strip && to >= Phase::Strip {
+        strip_binaries(options, target, listener, separate_debug_symbols)?;
+        if options.fix_rpath {
+            fix_rpaths(options, listener)?;
+        }
+    }
+
+    if to < Phase::BuildControl {
+        return Ok(None);
+    }
+
+    let system_time = resolve_timestamp(options.deterministic)?;
+    if options.deterministic {
+        // Walk order (glob, readdir) isn't guaranteed stable across filesystems/runs; sorting by
+        // the path each asset is installed to makes the resulting tar entry order depend only on
+        // the package contents, not on how they happened to be discovered.
+        options.assets.resolved.sort_by(|a, b| a.target_path.cmp(&b.target_path));
+    }
+    let (data_compressed, control_compressed) = build_control(options, system_time, listener)?;
+
+    if to < Phase::AssembleDeb {
+        return Ok(None);
+    }
+
+    Ok(Some(assemble_deb(options, system_time, data_compressed, control_compressed)?))
+}
+
+/// Picks the unix timestamp used to stamp every entry written to the `.deb` (ar member headers
+/// and `control.tar`/`data.tar` entries; the compressed envelopes around the tar members are
+/// written without a timestamp). Honors `SOURCE_DATE_EPOCH` per the reproducible-builds spec
+/// (<https://reproducible-builds.org/specs/source-date-epoch/>); with neither that nor
+/// `--deterministic` set, falls back to the current time as before.
+pub fn resolve_timestamp(deterministic: bool) -> CDResult<u64> {
+    if let Some(epoch) = env::var_os("SOURCE_DATE_EPOCH") {
+        return epoch.to_string_lossy().parse()
+            .map_err(|_| CargoDebError::Str("SOURCE_DATE_EPOCH must be a unix timestamp"));
+    }
+    if deterministic {
+        return Ok(0);
+    }
+    Ok(time::SystemTime::now().duration_since(time::UNIX_EPOCH)?.as_secs())
+}
+
+/// Generates the compressed `data.tar` and `control.tar` archive members ([`Phase::BuildControl`]).
+/// `control.tar` is generated after `data.tar`, since it embeds `data.tar`'s member `md5sums`.
+pub fn build_control(options: &Config, system_time: u64, listener: &mut dyn Listener) -> CDResult<(Compressed, Compressed)> {
+    let (data_compressed, original, asset_hashes) = crate::data::generate_archive(options, system_time, listener)?;
+    let control_compressed = crate::control::generate_archive(options, system_time, asset_hashes, listener)?;
+
+    let compressed = data_compressed.len() as u64;
+    listener.info(format!(
+        "compressed/original ratio {}/{} ({}%)",
+        compressed,
+        original,
+        compressed * 100 / original
+    ));
+    Ok((data_compressed, control_compressed))
+}
+
+/// Assembles the final `.deb` ar archive from already-built `data.tar`/`control.tar` members
+/// ([`Phase::AssembleDeb`]), returning its path.
+pub fn assemble_deb(options: &Config, system_time: u64, data_compressed: Compressed, control_compressed: Compressed) -> CDResult<PathBuf> {
+    let mut deb_contents = DebArchive::new(options)?;
+
+    deb_contents.add_data("debian-binary", system_time, b"2.0\n")?;
+
+    // Order is important for Debian
+    deb_contents.add_data(&control_compressed.member_name("control.tar"), system_time, &control_compressed)?;
+    drop(control_compressed);
+    deb_contents.add_data(&data_compressed.member_name("data.tar"), system_time, &data_compressed)?;
+    drop(data_compressed);
+
+    deb_contents.finish()
+}
+
+/// Builds a single `.deb` archive (control + data) for `options` and returns its path. Equivalent
+/// to running [`Phase::BuildControl`] then [`Phase::AssembleDeb`] via [`run_phases`].
+pub fn build_archive(options: &mut Config, listener: &mut dyn Listener) -> CDResult<PathBuf> {
+    let system_time = resolve_timestamp(options.deterministic)?;
+    if options.deterministic {
+        options.assets.resolved.sort_by(|a, b| a.target_path.cmp(&b.target_path));
+    }
+    let (data_compressed, control_compressed) = build_control(options, system_time, listener)?;
+    assemble_deb(options, system_time, data_compressed, control_compressed)
+}
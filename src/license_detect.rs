@@ -0,0 +1,116 @@
+//! Lightweight SPDX license-text fingerprinting.
+//!
+//! Used when `[package.metadata.deb] license-file` is given but the crate's `license` field is
+//! missing or might be wrong: matches the file's text against a small built-in corpus of common
+//! license texts, so `copyright`'s `License:` header can be filled in (or double-checked)
+//! without requiring an exact verbatim match (copyright holder, year and line-wrapping differ
+//! from project to project).
+
+use std::collections::HashSet;
+
+/// Shingle size (in words) used for the similarity comparison. Large enough to be specific to
+/// a particular license's wording, small enough to tolerate minor rewrapping/typos.
+const SHINGLE_SIZE: usize = 4;
+/// Minimum Sørensen–Dice score for a match to be reported at all.
+const MATCH_THRESHOLD: f32 = 0.5;
+/// Minimum score to consider a match confident enough not to warn about it.
+const CONFIDENT_THRESHOLD: f32 = 0.75;
+
+struct KnownLicense {
+    spdx_id: &'static str,
+    text: &'static str,
+}
+
+static KNOWN_LICENSES: &[KnownLicense] = &[
+    KnownLicense { spdx_id: "MIT", text: include_str!("../licenses/MIT.txt") },
+    KnownLicense { spdx_id: "Apache-2.0", text: include_str!("../licenses/Apache-2.0.txt") },
+    KnownLicense { spdx_id: "BSD-2-Clause", text: include_str!("../licenses/BSD-2-Clause.txt") },
+    KnownLicense { spdx_id: "BSD-3-Clause", text: include_str!("../licenses/BSD-3-Clause.txt") },
+    KnownLicense { spdx_id: "ISC", text: include_str!("../licenses/ISC.txt") },
+    KnownLicense { spdx_id: "MPL-2.0", text: include_str!("../licenses/MPL-2.0.txt") },
+];
+
+/// The result of matching a license file's text against the built-in corpus.
+pub(crate) struct Match {
+    pub spdx_id: &'static str,
+    pub confidence: f32,
+}
+
+impl Match {
+    pub fn is_confident(&self) -> bool {
+        self.confidence >= CONFIDENT_THRESHOLD
+    }
+}
+
+/// Normalizes license text into a lowercase word stream: drops lines that look like a
+/// copyright/holder line (so differing names/years don't affect the comparison), then splits on
+/// anything that isn't alphanumeric so punctuation and line-wrapping don't matter either.
+fn normalize(text: &str) -> Vec<String> {
+    text.lines()
+        .filter(|line| {
+            let lower = line.to_lowercase();
+            !(lower.contains("copyright") && lower.chars().any(|c| c.is_ascii_digit()))
+        })
+        .flat_map(|line| line.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// Builds the set of overlapping `n`-word shingles from a token stream.
+fn shingles(tokens: &[String], n: usize) -> HashSet<String> {
+    if tokens.len() < n {
+        return tokens.iter().cloned().collect();
+    }
+    tokens.windows(n).map(|window| window.join(" ")).collect()
+}
+
+/// Sørensen–Dice coefficient between two shingle sets: `2*|A∩B| / (|A|+|B|)`.
+fn dice_coefficient(a: &HashSet<String>, b: &HashSet<String>) -> f32 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let intersection = a.intersection(b).count() as f32;
+    (2.0 * intersection) / (a.len() + b.len()) as f32
+}
+
+/// Fingerprints `text` against the built-in license corpus and returns the best-scoring SPDX id,
+/// or `None` if nothing clears [`MATCH_THRESHOLD`].
+pub(crate) fn detect(text: &str) -> Option<Match> {
+    let input_shingles = shingles(&normalize(text), SHINGLE_SIZE);
+    KNOWN_LICENSES.iter()
+        .map(|known| {
+            let known_shingles = shingles(&normalize(known.text), SHINGLE_SIZE);
+            (known.spdx_id, dice_coefficient(&input_shingles, &known_shingles))
+        })
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .filter(|&(_, confidence)| confidence >= MATCH_THRESHOLD)
+        .map(|(spdx_id, confidence)| Match { spdx_id, confidence })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_exact_mit_text() {
+        let detected = detect(include_str!("../licenses/MIT.txt")).unwrap();
+        assert_eq!(detected.spdx_id, "MIT");
+        assert!(detected.is_confident());
+    }
+
+    #[test]
+    fn detects_mit_with_different_holder_and_year() {
+        let text = include_str!("../licenses/MIT.txt")
+            .replace("<year>", "2024")
+            .replace("<copyright holders>", "Jane Example and contributors");
+        let detected = detect(&text).unwrap();
+        assert_eq!(detected.spdx_id, "MIT");
+        assert!(detected.is_confident());
+    }
+
+    #[test]
+    fn does_not_match_unrelated_text() {
+        assert!(detect("This is just a README, not a license file at all.").is_none());
+    }
+}
@@ -1,17 +1,25 @@
+use cargo_deb::manifest::AssetSource;
 use cargo_deb::*;
 use std::env;
 use std::path::Path;
 use std::process;
-use std::time;
 
 struct CliOptions {
     no_build: bool,
     no_strip: bool,
+    no_fix_rpath: bool,
     separate_debug_symbols: bool,
+    dbgsym: bool,
+    dev_package: bool,
     fast: bool,
     verbose: bool,
     quiet: bool,
     install: bool,
+    list: bool,
+    diff: bool,
+    verify: bool,
+    all: bool,
+    exclude: Vec<String>,
     package_name: Option<String>,
     output_path: Option<String>,
     variant: Option<String>,
@@ -19,7 +27,13 @@ struct CliOptions {
     manifest_path: Option<String>,
     cargo_build_flags: Vec<String>,
     deb_version: Option<String>,
-    no_release: bool,
+    profile: String,
+    depends: Option<String>,
+    compress_type: Option<compress::Compression>,
+    compress_level: Option<u32>,
+    xz_dict_size: Option<u32>,
+    xz_threads: Option<u32>,
+    deterministic: bool,
 }
 
 fn main() {
@@ -28,20 +42,36 @@ fn main() {
     let mut cli_opts = getopts::Options::new();
     cli_opts.optflag("", "no-build", "Assume project is already built");
     cli_opts.optflag("", "no-strip", "Do not strip debug symbols from the binary");
+    cli_opts.optflag("", "no-fix-rpath", "Do not strip build-host RPATH/RUNPATH entries from the binary");
     cli_opts.optflag("", "separate-debug-symbols", "Strip debug symbols into a separate .debug file");
+    cli_opts.optflag("", "dbgsym", "Generate a separate <pkg>-dbgsym companion package instead of bundling debug symbols");
+    cli_opts.optflag("", "dev-package", "Also generate a lib<name>-dev companion package with a pkg-config file");
     cli_opts.optflag("", "fast", "Use faster compression, which yields larger archive");
     cli_opts.optflag("", "install", "Immediately install created package");
+    cli_opts.optflag("", "list", "Print package contents without building a .deb");
+    cli_opts.optflag("", "diff", "Print a diff of generated maintainer scripts against the package tree without building a .deb");
+    cli_opts.optflag("", "verify", "Re-open the built .deb and check its structure and contents before exiting successfully");
     cli_opts.optopt("", "target", "Rust target for cross-compilation", "triple");
     cli_opts.optopt("", "variant", "Alternative configuration section to use", "name");
     cli_opts.optopt("", "manifest-path", "Cargo project file location", "./Cargo.toml");
     cli_opts.optopt("p", "package", "Select one of packages belonging to a workspace", "name");
+    cli_opts.optflag("", "all", "Build a .deb for every workspace member with a [package.metadata.deb] section");
+    cli_opts.optflag("", "workspace", "Alias for --all");
+    cli_opts.optmulti("", "exclude", "Skip this workspace member when building with --all/--workspace", "name");
     cli_opts.optopt("o", "output", "Write .deb to this file or directory", "path");
     cli_opts.optflag("q", "quiet", "Don't print warnings");
     cli_opts.optflag("v", "verbose", "Print progress");
     cli_opts.optflag("h", "help", "Print this help menu");
     cli_opts.optflag("", "version", "Show the version of cargo-deb");
     cli_opts.optopt("", "deb-version", "Alternate version string for package", "version");
-    cli_opts.optflag("", "no-release", "Used in combination with 'no-build'. Assumes a none release build profile.");
+    cli_opts.optopt("", "profile", "Cargo build profile to package, e.g. release, dev, or a custom [profile.*] name", "name");
+    cli_opts.optflag("", "no-release", "Alias for --profile dev. Used in combination with 'no-build'.");
+    cli_opts.optopt("", "depends", "Override the package's Depends field; 'auto' derives it from linked shared libraries", "deps|auto");
+    cli_opts.optopt("", "compress-type", "Compression codec for the .deb's archives", "gzip|xz|zstd|none");
+    cli_opts.optopt("", "compress-level", "Compression level (xz: 0-9, zstd: 1-19)", "level");
+    cli_opts.optopt("", "xz-dict-size", "xz dictionary/window size in bytes, overriding the preset's own window (default 67108864, i.e. 64 MiB)", "bytes");
+    cli_opts.optopt("", "xz-threads", "Maximum number of xz compression threads; defaults to every available core", "count");
+    cli_opts.optflag("", "deterministic", "Make output reproducible: honor SOURCE_DATE_EPOCH, or fall back to a fixed timestamp");
 
 
     let matches = match cli_opts.parse(&args[1..]) {
@@ -60,14 +90,55 @@ fn main() {
         return;
     }
 
+    let compress_type = match matches.opt_str("compress-type") {
+        Some(s) => match s.parse() {
+            Ok(t) => Some(t),
+            Err(err) => err_exit(&err),
+        },
+        None => None,
+    };
+    let compress_level = match matches.opt_str("compress-level") {
+        Some(s) => match s.parse() {
+            Ok(l) => Some(l),
+            Err(_) => err_exit(&CargoDebError::Str("--compress-level must be a number")),
+        },
+        None => None,
+    };
+    let xz_dict_size = match matches.opt_str("xz-dict-size") {
+        Some(s) => match s.parse() {
+            Ok(n) => Some(n),
+            Err(_) => err_exit(&CargoDebError::Str("--xz-dict-size must be a number")),
+        },
+        None => None,
+    };
+    let xz_threads = match matches.opt_str("xz-threads") {
+        Some(s) => match s.parse() {
+            Ok(n) => Some(n),
+            Err(_) => err_exit(&CargoDebError::Str("--xz-threads must be a number")),
+        },
+        None => None,
+    };
+
+    let profile = matches.opt_str("profile").unwrap_or_else(|| {
+        if matches.opt_present("no-release") { "dev".to_owned() } else { "release".to_owned() }
+    });
+
     let install = matches.opt_present("install");
     match process(CliOptions {
         no_build: matches.opt_present("no-build"),
         no_strip: matches.opt_present("no-strip"),
+        no_fix_rpath: matches.opt_present("no-fix-rpath"),
         separate_debug_symbols: matches.opt_present("separate-debug-symbols"),
+        dbgsym: matches.opt_present("dbgsym"),
+        dev_package: matches.opt_present("dev-package"),
         quiet: matches.opt_present("quiet"),
         verbose: matches.opt_present("verbose"),
         install,
+        list: matches.opt_present("list"),
+        diff: matches.opt_present("diff"),
+        verify: matches.opt_present("verify"),
+        all: matches.opt_present("all") || matches.opt_present("workspace"),
+        exclude: matches.opt_strs("exclude"),
         // when installing locally it won't be transferred anywhere, so allow faster compression
         fast: install || matches.opt_present("fast"),
         variant: matches.opt_str("variant"),
@@ -76,7 +147,13 @@ fn main() {
         package_name: matches.opt_str("package"),
         manifest_path: matches.opt_str("manifest-path"),
         deb_version: matches.opt_str("deb-version"),
-        no_release: matches.opt_present("no-release"),
+        profile,
+        depends: matches.opt_str("depends"),
+        compress_type,
+        compress_level,
+        xz_dict_size,
+        xz_threads,
+        deterministic: matches.opt_present("deterministic"),
         cargo_build_flags: matches.free,
     }) {
         Ok(()) => {},
@@ -102,33 +179,45 @@ fn err_exit(err: &dyn std::error::Error) -> ! {
     process::exit(1);
 }
 
-fn process(
-    CliOptions {
-        manifest_path,
-        output_path,
-        package_name,
-        variant,
-        target,
-        install,
-        no_build,
-        no_strip,
-        separate_debug_symbols,
-        quiet,
-        fast,
-        verbose,
-        mut cargo_build_flags,
-        deb_version,
-        no_release,
-    }: CliOptions,
-) -> CDResult<()> {
-    let target = target.as_deref();
-    let variant = variant.as_deref();
+fn process(opts: CliOptions) -> CDResult<()> {
+    if opts.all {
+        let manifest_path = opts.manifest_path.as_ref().map_or("Cargo.toml", |s| s.as_str());
+        let members = Config::workspace_members_with_deb_metadata(Path::new(manifest_path))?
+            .into_iter()
+            .filter(|name| !opts.exclude.contains(name))
+            .collect::<Vec<_>>();
+        // `cargo_build` already passes `--all`, which builds every workspace member in one go,
+        // so the first member's build covers the rest too: cache its artifacts and hand the same
+        // list to every later member instead of re-invoking `cargo build` once per `.deb`.
+        let mut shared_artifacts = None;
+        for name in &members {
+            build_one(&opts, Some(name.as_str()), &mut shared_artifacts)?;
+        }
+        Ok(())
+    } else {
+        let package_name = opts.package_name.clone();
+        build_one(&opts, package_name.as_deref(), &mut None)
+    }
+}
+
+/// Builds and (optionally) installs a single `.deb`, for `package_name` (or the workspace
+/// root package when `None`). Called once per `--all`/`--workspace` member, or once otherwise.
+/// `shared_artifacts` caches the first `cargo build` invocation's output across repeated calls
+/// from a workspace build, since `cargo_build` builds the whole workspace regardless of which
+/// member's `Config` it's called with.
+fn build_one(opts: &CliOptions, package_name: Option<&str>, shared_artifacts: &mut Option<Vec<BuiltArtifact>>) -> CDResult<()> {
+    let target = opts.target.as_deref();
+    let variant = opts.variant.as_deref();
+    let quiet = opts.quiet;
+    let fast = opts.fast;
+    let install = opts.install;
 
     if install || target.is_none() {
         warn_if_not_linux(); // compiling natively for non-linux = nope
     }
 
     // `cargo deb` invocation passes the `deb` arg through.
+    let mut cargo_build_flags = opts.cargo_build_flags.clone();
     if cargo_build_flags.first().map_or(false, |arg| arg == "deb") {
         cargo_build_flags.remove(0);
     }
@@ -140,75 +229,135 @@ fn process(
         listener_tmp1 = listener::NoOpListener;
         &mut listener_tmp1
     } else {
-        listener_tmp2 = listener::StdErrListener { verbose };
+        listener_tmp2 = listener::StdErrListener { verbose: opts.verbose };
         &mut listener_tmp2
     };
 
-    let manifest_path = manifest_path.as_ref().map_or("Cargo.toml", |s| s.as_str());
+    let manifest_path = opts.manifest_path.as_ref().map_or("Cargo.toml", |s| s.as_str());
     let mut options = Config::from_manifest(
         Path::new(manifest_path),
-        package_name.as_deref(),
-        output_path,
+        package_name,
+        opts.output_path.clone(),
         target,
         variant,
-        deb_version,
+        opts.deb_version.clone(),
         listener,
-        no_release,
+        &opts.profile,
     )?;
+    if let Some(ref depends) = opts.depends {
+        // "auto" is friendlier to type on the command line than the Cargo.toml sentinel.
+        options.depends = if depends == "auto" { "$auto".to_owned() } else { depends.clone() };
+    }
+    if let Some(compress_type) = opts.compress_type {
+        options.compress_type = compress_type;
+    } else if fast {
+        // --fast/--install didn't ask for a specific codec, so keep its historical meaning:
+        // gzip is much quicker to produce than xz, at the cost of a larger archive.
+        options.compress_type = compress::Compression::Gzip;
+    }
+    if opts.compress_level.is_some() {
+        options.compress_level = opts.compress_level;
+    } else if fast && options.compress_type == compress::Compression::Gzip {
+        // --fast also means "don't spend ages in zopfli"; pick flate2's fastest quality instead.
+        options.compress_level = Some(1);
+    }
+    if opts.xz_dict_size.is_some() {
+        options.xz_compression.dict_size = opts.xz_dict_size;
+    }
+    if opts.xz_threads.is_some() {
+        options.xz_compression.threads = opts.xz_threads;
+    }
+    // SOURCE_DATE_EPOCH being set is itself a request for reproducible output, even without
+    // --deterministic, per https://reproducible-builds.org/specs/source-date-epoch/.
+    options.deterministic = opts.deterministic || env::var_os("SOURCE_DATE_EPOCH").is_some();
     reset_deb_temp_directory(&options)?;
 
-    if !no_build {
-        cargo_build(&options, target, &cargo_build_flags, verbose)?;
+    // Tracks the temp dir and the `.deb`s we're about to write, so any early return (including a
+    // `?` from one of the build steps below) cleans up the partial output instead of leaving it
+    // for the next run to trip over. Cleared by `transaction.commit()` once everything succeeds.
+    let mut transaction = Transaction::new();
+    transaction.track(options.deb_temp_dir());
+
+    if !opts.no_build {
+        let built_artifacts = match shared_artifacts {
+            Some(artifacts) => artifacts.clone(),
+            None => {
+                let artifacts = cargo_build(&options, target, &cargo_build_flags, opts.verbose)?;
+                *shared_artifacts = Some(artifacts.clone());
+                artifacts
+            },
+        };
+        options.apply_build_artifacts(&built_artifacts);
     }
 
     options.resolve_assets()?;
 
-    crate::data::compress_assets(&mut options, listener)?;
+    if opts.list {
+        options.add_debug_assets();
+        list_contents(&options);
+        remove_deb_temp_directory(&options);
+        return Ok(());
+    }
+
+    if opts.diff {
+        let any_diff = diff::diff_maintainer_scripts(&options, listener)?;
+        remove_deb_temp_directory(&options);
+        if any_diff {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    crate::data::compress_documentation(&mut options, listener)?;
 
-    if (options.strip || separate_debug_symbols) && !no_strip {
-        strip_binaries(&mut options, target, listener, separate_debug_symbols)?;
+    if (options.strip || opts.separate_debug_symbols || opts.dbgsym) && !opts.no_strip {
+        strip_binaries(&mut options, target, listener, opts.separate_debug_symbols || opts.dbgsym)?;
     }
 
-    // Obtain the current time which will be used to stamp the generated files in the archives.
-    let system_time = time::SystemTime::now().duration_since(time::UNIX_EPOCH)?.as_secs();
-    let mut deb_contents = DebArchive::new(&options)?;
+    if options.fix_rpath && !opts.no_fix_rpath {
+        fix_rpaths(&options, listener)?;
+    }
 
-    deb_contents.add_data("debian-binary", system_time, b"2.0\n")?;
+    let dbgsym_config = if opts.dbgsym { options.split_dbgsym_package() } else { None };
+    let dev_config = if opts.dev_package { options.split_dev_package() } else { None };
 
-    // Initailize the contents of the data archive (files that go into the filesystem).
-    let (data_archive, asset_hashes) = data::generate_archive(&options, system_time, listener)?;
-    let original = data_archive.len();
+    transaction.track(options.deb_output_path(&options.deb_output_filename()));
+    if let Some(dbgsym_config) = &dbgsym_config {
+        transaction.track(dbgsym_config.deb_output_path(&dbgsym_config.deb_output_filename()));
+    }
+    if let Some(dev_config) = &dev_config {
+        transaction.track(dev_config.deb_output_path(&dev_config.deb_output_filename()));
+    }
 
-    let listener_tmp = &mut *listener; // reborrow for the closure
-    let options = &options;
-    let (control_compressed, data_compressed) = rayon::join(move || {
-        // The control archive is the metadata for the package manager
-        let control_archive = control::generate_archive(options, system_time, asset_hashes, listener_tmp)?;
-        compress::xz_or_gz(&control_archive, fast)
-    }, move || {
-        compress::xz_or_gz(&data_archive, fast)
-    });
-    let control_compressed = control_compressed?;
-    let data_compressed = data_compressed?;
-
-    // Order is important for Debian
-    deb_contents.add_data(&format!("control.tar.{}", control_compressed.extension()), system_time, &control_compressed)?;
-    drop(control_compressed);
-    let compressed = data_compressed.len();
-    listener.info(format!(
-        "compressed/original ratio {}/{} ({}%)",
-        compressed,
-        original,
-        compressed * 100 / original
-    ));
-    deb_contents.add_data(&format!("data.tar.{}", data_compressed.extension()), system_time, &data_compressed)?;
-    drop(data_compressed);
-
-    let generated = deb_contents.finish()?;
+    let generated = build_archive(&mut options, &mut *listener)?;
+    if opts.verify {
+        verify::verify(&generated, listener)?;
+    }
     if !quiet {
         println!("{}", generated.display());
     }
 
+    if let Some(mut dbgsym_config) = dbgsym_config {
+        let dbgsym_generated = build_archive(&mut dbgsym_config, &mut *listener)?;
+        if opts.verify {
+            verify::verify(&dbgsym_generated, listener)?;
+        }
+        if !quiet {
+            println!("{}", dbgsym_generated.display());
+        }
+    }
+
+    if let Some(mut dev_config) = dev_config {
+        let dev_generated = build_archive(&mut dev_config, &mut *listener)?;
+        if opts.verify {
+            verify::verify(&dev_generated, listener)?;
+        }
+        if !quiet {
+            println!("{}", dev_generated.display());
+        }
+    }
+
+    transaction.commit();
     remove_deb_temp_directory(&options);
 
     if install {
@@ -217,6 +366,20 @@ fn process(
     Ok(())
 }
 
+/// Prints the archive target path, source kind, mode and size of every resolved asset,
+/// without invoking the compiler or writing a `.deb`.
+fn list_contents(options: &Config) {
+    for asset in &options.assets.resolved {
+        let kind = match asset.source {
+            AssetSource::Path(_) => "path",
+            AssetSource::Data(_) => "data",
+            AssetSource::Symlink(_) => "link",
+        };
+        let size = asset.source.len().map(|len| len.to_string()).unwrap_or_else(|| "?".to_owned());
+        println!("{:o} {:>10} {:<4} /{}", asset.chmod, size, kind, asset.target_path.display());
+    }
+}
+
 #[cfg(target_os = "linux")]
 fn warn_if_not_linux() {}
 
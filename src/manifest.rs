@@ -1,12 +1,12 @@
 use crate::config::CargoConfig;
-use crate::dependencies::resolve;
+use crate::dependencies::resolve_many;
 use crate::error::*;
 use crate::listener::Listener;
 use crate::ok_or::OkOrThen;
+use cargo_metadata;
 use cargo_toml;
 use glob;
 use serde_derive::Deserialize;
-use serde_json;
 use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
@@ -14,18 +14,111 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use toml;
-use rayon::prelude::*;
 
 fn is_glob_pattern(s: &str) -> bool {
     s.contains('*') || s.contains('[') || s.contains(']') || s.contains('!')
 }
 
+/// Parses an asset's fourth array entry, `"user:group"`, into an [`Owner`]. Numeric uid/gid are
+/// left at 0 since `uname`/`gname` is what `dpkg` actually resolves against the target system at
+/// unpack time.
+fn parse_asset_owner(spec: &str) -> CDResult<Owner> {
+    let (uname, gname) = spec.split_once(':')
+        .ok_or("asset owner must be in \"user:group\" form")?;
+    Ok(Owner {
+        uid: 0,
+        gid: 0,
+        uname: uname.to_owned(),
+        gname: gname.to_owned(),
+    })
+}
+
+/// Bit number of a named Linux capability, per `linux/capability.h`.
+fn capability_bit(name: &str) -> CDResult<u8> {
+    Ok(match name {
+        "cap_chown" => 0,
+        "cap_dac_override" => 1,
+        "cap_dac_read_search" => 2,
+        "cap_fowner" => 3,
+        "cap_fsetid" => 4,
+        "cap_kill" => 5,
+        "cap_setgid" => 6,
+        "cap_setuid" => 7,
+        "cap_setpcap" => 8,
+        "cap_linux_immutable" => 9,
+        "cap_net_bind_service" => 10,
+        "cap_net_broadcast" => 11,
+        "cap_net_admin" => 12,
+        "cap_net_raw" => 13,
+        "cap_ipc_lock" => 14,
+        "cap_ipc_owner" => 15,
+        "cap_sys_module" => 16,
+        "cap_sys_rawio" => 17,
+        "cap_sys_chroot" => 18,
+        "cap_sys_ptrace" => 19,
+        "cap_sys_pacct" => 20,
+        "cap_sys_admin" => 21,
+        "cap_sys_boot" => 22,
+        "cap_sys_nice" => 23,
+        "cap_sys_resource" => 24,
+        "cap_sys_time" => 25,
+        "cap_sys_tty_config" => 26,
+        "cap_mknod" => 27,
+        "cap_lease" => 28,
+        "cap_audit_write" => 29,
+        "cap_audit_control" => 30,
+        "cap_setfcap" => 31,
+        other => return Err(CargoDebError::UnknownCapability(other.to_owned())),
+    })
+}
+
+/// Encodes a set of capability bit numbers as a `security.capability` xattr value in the
+/// `VFS_CAP_REVISION_2` format the kernel (and thus `dpkg-deb`/`dpkg --unpack`) understands,
+/// with every named capability set as both effective and permitted.
+///
+/// # References
+///
+/// https://man7.org/linux/man-pages/man7/capabilities.7.html (the "File capability extended attribute" section)
+fn encode_capability_xattr(caps: &[u8]) -> Vec<u8> {
+    const VFS_CAP_REVISION_2: u32 = 0x0200_0000;
+    const VFS_CAP_FLAGS_EFFECTIVE: u32 = 0x0000_0001;
+
+    let mut permitted = [0u32; 2];
+    for &cap in caps {
+        permitted[(cap / 32) as usize] |= 1 << (cap % 32);
+    }
+
+    let mut out = Vec::with_capacity(20);
+    out.extend_from_slice(&(VFS_CAP_REVISION_2 | VFS_CAP_FLAGS_EFFECTIVE).to_le_bytes());
+    out.extend_from_slice(&permitted[0].to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes()); // inheritable, low
+    out.extend_from_slice(&permitted[1].to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes()); // inheritable, high
+    out
+}
+
+/// Parses an asset's fifth array entry, e.g. `"cap_net_bind_service+ep"`, into an encoded
+/// `security.capability` xattr value. Only the common `+ep` (effective and permitted) form used
+/// by `setcap` is supported; capabilities without `permitted` can't be effective anyway.
+fn parse_asset_capabilities(spec: &str) -> CDResult<Vec<u8>> {
+    let (names, flags) = spec.split_once('+')
+        .ok_or("asset capabilities must be in \"cap_name[,cap_name...]+ep\" form")?;
+    if flags != "ep" && flags != "pe" {
+        return Err("only the +ep (effective and permitted) capability flags are supported".into());
+    }
+    let caps = names.split(',').map(capability_bit).collect::<CDResult<Vec<_>>>()?;
+    Ok(encode_capability_xattr(&caps))
+}
+
 #[derive(Debug, Clone)]
 pub enum AssetSource {
     /// Copy file from the path (and strip binary if needed).
     Path(PathBuf),
     /// Write data to destination as-is.
     Data(Vec<u8>),
+    /// Create a symlink in the archive pointing at the given (relative) target,
+    /// e.g. an unversioned `libfoo.so` pointing at the real `libfoo.so.1`.
+    Symlink(PathBuf),
 }
 
 impl AssetSource {
@@ -41,6 +134,7 @@ impl AssetSource {
             // FIXME: may not be accurate if the executable is not stripped yet?
             AssetSource::Path(ref p) => fs::metadata(p).ok().map(|m| m.len()),
             AssetSource::Data(ref d) => Some(d.len() as u64),
+            AssetSource::Symlink(_) => Some(0),
         }
     }
 
@@ -54,6 +148,7 @@ impl AssetSource {
             AssetSource::Data(ref d) => {
                 Cow::Borrowed(d)
             },
+            AssetSource::Symlink(_) => Cow::Borrowed(&[]),
         })
     }
 
@@ -100,12 +195,27 @@ impl Assets {
     }
 }
 
+/// Explicit ownership for an installed asset, overriding the `root:root` every other tar entry
+/// gets. Written to the tar header's uid/gid/uname/gname fields verbatim; `dpkg` resolves
+/// `uname`/`gname` against the target system's accounts at unpack time (falling back to the
+/// numeric `uid`/`gid` if the name doesn't exist there), so a service account created by a
+/// `usr/lib/sysusers.d/*.conf` asset this same package installs is the common pairing.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+    pub uname: String,
+    pub gname: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct UnresolvedAsset {
     pub source_path: PathBuf,
     pub target_path: PathBuf,
     pub chmod: u32,
     pub is_built: bool,
+    pub owner: Option<Owner>,
+    pub capabilities: Option<Vec<u8>>,
 }
 
 #[derive(Debug, Clone)]
@@ -113,6 +223,10 @@ pub struct Asset {
     pub source: AssetSource,
     pub target_path: PathBuf,
     pub chmod: u32,
+    pub owner: Option<Owner>,
+    /// Encoded `security.capability` xattr value (see [`parse_asset_capabilities`]), installed on
+    /// this asset so e.g. a `cap_net_bind_service` binary doesn't need a postinst `setcap` call.
+    pub capabilities: Option<Vec<u8>>,
     is_built: bool,
 }
 
@@ -132,10 +246,25 @@ impl Asset {
             source,
             target_path,
             chmod,
+            owner: None,
+            capabilities: None,
             is_built,
         }
     }
 
+    /// Overrides the `root:root` ownership this asset would otherwise get in the archive.
+    pub fn with_owner(mut self, owner: Option<Owner>) -> Self {
+        self.owner = owner;
+        self
+    }
+
+    /// Sets the Linux capabilities (e.g. `cap_net_bind_service`) installed on this asset via a
+    /// `security.capability` xattr in the archive.
+    pub fn with_capabilities(mut self, capabilities: Option<Vec<u8>>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
     fn is_executable(&self) -> bool {
         0 != (self.chmod & 0o111)
     }
@@ -143,7 +272,8 @@ impl Asset {
     fn is_dynamic_library(&self) -> bool {
         self.target_path.file_name()
             .and_then(|f| f.to_str())
-            .map_or(false, |f| f.ends_with(DLL_SUFFIX))
+            // also matches SONAME-versioned libraries, e.g. libfoo.so.1
+            .map_or(false, |f| f.ends_with(DLL_SUFFIX) || f.contains(&format!("{}.", DLL_SUFFIX)))
     }
 
     /// Returns the target path for the debug symbol file, which will be
@@ -176,7 +306,87 @@ fn debug_filename(path: &Path) -> PathBuf {
     Path::new(&debug_filename).to_path_buf()
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+/// Git commit hash and working-tree cleanliness at packaging time.
+pub(crate) struct VcsInfo {
+    pub commit: String,
+    pub dirty: bool,
+}
+
+/// Resolved settings controlling `dh_installsystemd`-equivalent autoscript generation, i.e. the
+/// subset of `dh_installsystemd`'s command line switches that affect how installed systemd units
+/// (service, socket, timer, path, mount and target units) are enabled, started, stopped and
+/// disabled by the package's maintainer scripts.
+#[derive(Debug, Clone, Default)]
+pub struct SystemdUnitsConfig {
+    /// Overrides the unit name `dh_installsystemd` would otherwise derive from the package name.
+    /// Corresponds to `dh_installsystemd --name`.
+    pub unit_name: Option<String>,
+    /// Enable the unit(s) on install, and disable (and mask, on purge) them when uninstalled.
+    /// Corresponds to the absence of `dh_installsystemd --no-enable`.
+    pub enable: bool,
+    /// Start the unit(s) after install and upgrades. Corresponds to the absence of
+    /// `dh_installsystemd --no-start`.
+    pub start: bool,
+    /// Don't stop the unit(s) until after the package upgrade has completed, instead of
+    /// stopping before and starting again after. Corresponds to `dh_installsystemd
+    /// --restart-after-upgrade`, which has been the default since debhelper compat 10.
+    pub restart_after_upgrade: bool,
+    /// Don't stop or restart the unit(s) across upgrades at all. Corresponds to
+    /// `dh_installsystemd --no-stop-on-upgrade`/`-r`.
+    pub no_stop_on_upgrade: bool,
+    /// Unit (base) names to skip entirely: they're still installed, but no maintainer-script
+    /// fragments are generated for them. Corresponds to `dh_installsystemd -X`.
+    pub exclude: Vec<String>,
+    /// When set, restricts maintainer-script generation to exactly these unit (base) names; any
+    /// other installed unit is still installed but otherwise treated as excluded. Corresponds to
+    /// passing an explicit `unit file ...` list to `dh_installsystemd`.
+    pub only_units: Option<Vec<String>>,
+    /// The debhelper compat level to emulate. At 13 and above, tmpfiles handling switches to the
+    /// `dh_installtmpfiles`-style `systemd-tmpfiles --create` invocation and `preinst` fragments
+    /// are generated to unmask previously-masked units; below 13 the older inline
+    /// `postinst-init-tmpfiles` snippet is used and no `preinst` fragments are produced.
+    pub compat: u32,
+    /// Suppress the informational warning normally emitted for a unit that is started but has no
+    /// `[Install]` section (a "static" unit, e.g. D-Bus-activated services like colord) and is
+    /// therefore not enabled. Set this for packages that intentionally ship such units.
+    pub no_static_unit_warnings: bool,
+    /// Per-unit (base name, e.g. `"myunit.service"`) overrides of `enable`, layered on top of the
+    /// package-wide default above. Finer-grained than `exclude`/`only_units`: those drop a unit's
+    /// maintainer-script handling entirely, whereas this lets e.g. one auxiliary unit ship
+    /// disabled-by-default while the rest of the package's units are enabled as usual.
+    pub unit_overrides: HashMap<String, UnitOverride>,
+    /// Don't emit the `postinst` call that runs `systemd-tmpfiles --create` for any installed
+    /// `usr/lib/tmpfiles.d/*.conf` assets. Set this for packages that ship such a file purely as
+    /// documentation/reference rather than for it to be acted on.
+    pub no_tmpfiles: bool,
+    /// Don't emit the `postinst` call that runs `systemd-sysusers` for any installed
+    /// `usr/lib/sysusers.d/*.conf` assets.
+    pub no_sysusers: bool,
+}
+
+/// A per-unit override of one or more package-wide [`SystemdUnitsConfig`] defaults.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnitOverride {
+    pub enable: Option<bool>,
+}
+
+/// Resolved settings for generating a `debian/tests/control`-equivalent autopkgtest definition,
+/// so the `.deb` this produces can be exercised by the Debian CI harness.
+#[derive(Debug, Clone, Default)]
+pub struct AutopkgtestConfig {
+    /// Shell commands to run as `Test-Command:` stanzas, in the order given.
+    pub test_commands: Vec<String>,
+    /// Binary packages whose upload should also trigger this package's tests. Written as the
+    /// `Testsuite-Triggers` control field.
+    pub triggers: Vec<String>,
+    /// Skip the built-in smoke test that `systemctl status`-checks every system unit this
+    /// package installs under `lib/systemd/system/` (user units are not checked). Has no effect
+    /// on a package that installs no such units.
+    pub skip_systemd_smoke_test: bool,
+}
+
+#[derive(Debug, Clone)]
 /// Cargo deb configuration read from the manifest and cargo metadata
 pub struct Config {
     /// Root directory where `Cargo.toml` is located. It's a subdirectory in workspaces.
@@ -187,6 +397,10 @@ pub struct Config {
     pub target: Option<String>,
     /// `CARGO_TARGET_DIR`
     pub target_dir: PathBuf,
+    /// Cargo build profile to package, e.g. `"release"`, `"dev"`, or a custom profile name from
+    /// `[profile.<name>]`. Set from `--profile` (or `--no-release`, a back-compat alias for
+    /// `--profile dev`); defaults to `"release"`.
+    pub build_profile: String,
     /// The name of the project to build
     pub name: String,
     /// The name to give the Debian package; usually the same as the Cargo project name
@@ -218,6 +432,15 @@ pub struct Config {
     pub maintainer: String,
     /// The Debian dependencies required to run the project.
     pub depends: String,
+    /// Debian packages derived from `[package.metadata.deb.dependencies]` by walking the
+    /// resolved Cargo dependency graph. Merged into `Depends` wherever `depends` has `$auto`.
+    pub(crate) auto_depends: Vec<String>,
+    /// Whether `copyright` should carry one DEP-5 paragraph per dependency crate, in addition
+    /// to the crate's own. See `[package.metadata.deb] separate-dep-licenses`.
+    pub separate_dep_licenses: bool,
+    /// Collected per-dependency license paragraphs, populated only when
+    /// `separate_dep_licenses` is set.
+    pub(crate) dependency_license_notices: Vec<DependencyLicenseNotice>,
     /// The Debian software category to which the package belongs.
     pub section: Option<String>,
     /// The Debian priority of the project. Typically 'optional'.
@@ -239,6 +462,8 @@ pub struct Config {
     ///
     /// See [PackageTransition](https://wiki.debian.org/PackageTransition).
     pub provides: Option<String>,
+    /// `Recommends` Debian control field.
+    pub recommends: Option<String>,
 
     /// The Debian architecture of the target system.
     pub architecture: String,
@@ -248,6 +473,16 @@ pub struct Config {
     pub(crate) assets: Assets,
     /// The path were possible maintainer scripts live
     pub maintainer_scripts: Option<PathBuf>,
+    /// Extra files (besides the maintainer scripts themselves) whose content should invalidate
+    /// the fingerprint cached for generated maintainer scripts, so hand-maintained fragments
+    /// included or referenced by them also trigger regeneration when edited.
+    pub(crate) maintainer_scripts_rerun_if_changed: Vec<PathBuf>,
+    /// When set, drives `dh_installsystemd`-equivalent autoscript generation (enabling,
+    /// starting, stopping and disabling systemd units) for this package's maintainer scripts.
+    pub systemd_units: Option<SystemdUnitsConfig>,
+    /// When set, drives generation of a `debian/tests/control`-equivalent autopkgtest definition
+    /// and the `Testsuite`/`Testsuite-Triggers` control fields.
+    pub autopkgtest: Option<AutopkgtestConfig>,
     /// List of Cargo features to use during build
     pub features: Vec<String>,
     pub default_features: bool,
@@ -255,6 +490,41 @@ pub struct Config {
     pub strip: bool,
     /// Should the debug symbols be moved to a separate file included in the package? (implies `strip:true`)
     pub separate_debug_symbols: bool,
+    /// Should `DT_RPATH`/`DT_RUNPATH` entries pointing at the build machine be stripped from
+    /// packaged ELF binaries? On by default, since a leftover build-host path is both a reproducibility
+    /// hazard and useless (or actively wrong) once the binary is installed under `/usr/bin`.
+    pub fix_rpath: bool,
+    /// `Auto-Built-Package` Debian control field, set on companion packages such as `-dbgsym`.
+    pub(crate) auto_built_package: Option<String>,
+    /// Assets matching any of these patterns are dropped from the resolved asset list.
+    pub(crate) asset_excludes: Vec<glob::Pattern>,
+    /// When non-empty, only assets matching one of these patterns are kept.
+    pub(crate) asset_includes: Vec<glob::Pattern>,
+    /// Documentation assets matching any of these patterns are skipped by
+    /// `data::compress_documentation`, even if their path/size would otherwise qualify.
+    pub(crate) compress_doc_exclude: Vec<glob::Pattern>,
+    /// Git commit/dirty state of `manifest_dir`, captured for reproducible-build provenance.
+    pub(crate) vcs_info: Option<VcsInfo>,
+    /// Pre-formatted Debian `shlibs` lines (`library major-version package (>= version)`) for
+    /// this package's SONAME-versioned shared libraries, written to the control archive as-is.
+    pub(crate) shlibs: Vec<String>,
+    /// Codec used for the `control.tar`/`data.tar` members and for individually compressed
+    /// assets (changelog, man pages). Set from `--compress-type`; defaults to `xz`.
+    pub compress_type: crate::compress::Compression,
+    /// Codec-specific quality knob (xz preset 0-9, zstd level 1-19) for `compress_type`.
+    /// Set from `--compress-level`; `None` means the codec's own default.
+    pub compress_level: Option<u32>,
+    /// Tunable xz dictionary size/thread cap, consulted only when `compress_type` is `Xz`.
+    pub xz_compression: crate::compress::XzCompressionSettings,
+    /// Set from `--deterministic` (or implicitly by `SOURCE_DATE_EPOCH` being present in the
+    /// environment). When true, the resolved asset list is sorted by `target_path` before being
+    /// archived, so `control.tar`/`data.tar` are bit-for-bit identical across rebuilds of the
+    /// same source tree regardless of directory-walk order.
+    pub deterministic: bool,
+    /// When true, `data.tar` assets with byte-identical content are archived as tar hardlinks to
+    /// the first copy instead of being stored (and compressed) again. Set from
+    /// `[package.metadata.deb] hardlink-dedup`.
+    pub hardlink_dedup: bool,
     _use_constructor_to_make_this_struct_: (),
 }
 
@@ -262,7 +532,7 @@ impl Config {
     /// Makes a new config from `Cargo.toml` in the current working directory.
     ///
     /// `None` target means the host machine's architecture.
-    pub fn from_manifest(manifest_path: &Path, package_name: Option<&str>, output_path: Option<String>, target: Option<&str>, variant: Option<&str>, deb_version: Option<String>, listener: &dyn Listener) -> CDResult<Config> {
+    pub fn from_manifest(manifest_path: &Path, package_name: Option<&str>, output_path: Option<String>, target: Option<&str>, variant: Option<&str>, deb_version: Option<String>, listener: &dyn Listener, build_profile: &str) -> CDResult<Config> {
         let metadata = cargo_metadata(manifest_path)?;
         let available_package_names = || {
             metadata.packages.iter()
@@ -276,18 +546,38 @@ impl Config {
             })
             .ok_or_else(|| CargoDebError::PackageNotFoundInWorkspace(name.into(), available_package_names()))
         } else {
-            metadata.resolve.root.as_ref().and_then(|root_id| {
+            metadata.resolve.as_ref().and_then(|r| r.root.as_ref()).and_then(|root_id| {
                 metadata.packages.iter()
                     .find(|p| &p.id == root_id)
             })
             .ok_or_else(|| CargoDebError::NoRootFoundInWorkspace(available_package_names()))
         }?;
-        let target_dir = Path::new(&metadata.target_directory);
-        let manifest_path = Path::new(&root_package.manifest_path);
+        let target_dir = metadata.target_directory.as_std_path();
+        let manifest_path = root_package.manifest_path.as_std_path();
         let manifest_dir = manifest_path.parent().unwrap();
         let content = fs::read(&manifest_path)
             .map_err(|e| CargoDebError::IoFile("unable to read Cargo.toml", e, manifest_path.to_owned()))?;
-        toml::from_slice::<Cargo>(&content)?.into_config(root_package, manifest_dir, output_path, target_dir, target, variant, deb_version, listener)
+        toml::from_slice::<Cargo>(&content)?.into_config(root_package, &metadata, manifest_dir, output_path, target_dir, target, variant, deb_version, listener, build_profile)
+    }
+
+    /// Returns the names of every workspace member that declares a `[package.metadata.deb]`
+    /// section, for `--all` builds that produce one `.deb` per member.
+    pub fn workspace_members_with_deb_metadata(manifest_path: &Path) -> CDResult<Vec<String>> {
+        let metadata = cargo_metadata(manifest_path)?;
+        let mut names = Vec::new();
+        for package in &metadata.packages {
+            if !metadata.workspace_members.iter().any(|w| w == &package.id) {
+                continue;
+            }
+            let member_manifest_path = package.manifest_path.as_std_path();
+            let content = fs::read(member_manifest_path)
+                .map_err(|e| CargoDebError::IoFile("unable to read Cargo.toml", e, member_manifest_path.to_owned()))?;
+            let cargo: Cargo = toml::from_slice(&content)?;
+            if cargo.package.metadata.and_then(|m| m.deb).is_some() {
+                names.push(package.name.clone());
+            }
+        }
+        Ok(names)
     }
 
     pub(crate) fn get_dependencies(&self, listener: &dyn Listener) -> CDResult<String> {
@@ -296,28 +586,26 @@ impl Config {
             let word = word.trim();
             if word == "$auto" {
                 let bin = self.all_binaries();
-                let resolved = bin.par_iter()
-                    .filter_map(|p| p.path())
-                    .filter_map(|bname| match resolve(bname, &self.architecture, listener) {
-                        Ok(bindeps) => Some(bindeps),
-                        Err(err) => {
-                            listener.warning(format!("{} (no auto deps for {})", err, bname.display()));
-                            None
-                        },
-                    })
-                    .collect::<Vec<_>>();
-                for dep in resolved.into_iter().flat_map(|s| s.into_iter()) {
+                let paths: Vec<&Path> = bin.iter().filter_map(|p| p.path()).collect();
+                for dep in resolve_many(&paths, listener) {
                     deps.insert(dep);
                 }
+                for dep in &self.auto_depends {
+                    deps.insert(dep.clone());
+                }
             } else {
                 deps.insert(word.to_owned());
             }
         }
-        Ok(deps.into_iter().collect::<Vec<_>>().join(", "))
+        let mut deps: Vec<_> = deps.into_iter().collect();
+        // `HashSet` iteration order is randomized per process, which would otherwise make the
+        // `Depends:` field's word order non-deterministic across runs of the same build.
+        deps.sort();
+        Ok(deps.join(", "))
     }
 
     pub fn resolve_assets(&mut self) -> CDResult<()> {
-        for UnresolvedAsset { source_path, target_path, chmod, is_built } in self.assets.unresolved.drain(..) {
+        for UnresolvedAsset { source_path, target_path, chmod, is_built, owner, capabilities } in self.assets.unresolved.drain(..) {
             let source_prefix: PathBuf = source_path.iter()
                 .take_while(|part| !is_glob_pattern(part.to_str().unwrap()))
                 .collect();
@@ -339,6 +627,12 @@ impl Config {
                 })
                 .collect::<CDResult<Vec<_>>>()?;
 
+            // Subtract files excluded by `exclude`/`include` patterns before checking for an empty match
+            let file_matches: Vec<_> = file_matches.into_iter()
+                .filter(|source_file| !self.asset_excludes.iter().any(|pat| pat.matches_path(source_file)))
+                .filter(|source_file| self.asset_includes.is_empty() || self.asset_includes.iter().any(|pat| pat.matches_path(source_file)))
+                .collect();
+
             // If glob didn't match anything, it's likely an error
             // as all files should exist when called to resolve
             if file_matches.is_empty() {
@@ -357,14 +651,14 @@ impl Config {
                     target_file,
                     chmod,
                     is_built,
-                ));
+                ).with_owner(owner.clone()).with_capabilities(capabilities.clone()));
             }
         }
         Ok(())
     }
 
-    pub(crate) fn add_copyright_asset(&mut self) -> CDResult<()> {
-        let copyright_file = crate::data::generate_copyright_asset(self)?;
+    pub(crate) fn add_copyright_asset(&mut self, listener: &dyn Listener) -> CDResult<()> {
+        let copyright_file = crate::data::generate_copyright_asset(self, listener)?;
         self.assets.resolved.push(Asset::new(
             AssetSource::Data(copyright_file),
             Path::new("usr/share/doc")
@@ -393,15 +687,109 @@ impl Config {
         self.assets.resolved.append(&mut assets_to_add);
     }
 
+    /// Builds a `<pkg>-dbgsym` companion `Config`, carrying only the debug symbol assets for
+    /// binaries that have already been stripped with `separate_file: true`, and referencing
+    /// the main package via a versioned `Depends`. Returns `None` if there's nothing to debug.
+    pub fn split_dbgsym_package(&self) -> Option<Config> {
+        let mut assets = Vec::new();
+        for asset in self.built_binaries().into_iter().filter(|a| a.source.path().is_some()) {
+            let debug_source = asset.source.debug_source().unwrap();
+            if debug_source.exists() {
+                let debug_target = build_id_debug_target(&debug_source)
+                    .unwrap_or_else(|| asset.debug_target().unwrap());
+                assets.push(Asset::new(AssetSource::Path(debug_source), debug_target, 0o644, false));
+            }
+        }
+        if assets.is_empty() {
+            return None;
+        }
+
+        let mut dbgsym = self.clone();
+        dbgsym.deb_name = format!("{}-dbgsym", self.deb_name);
+        dbgsym.depends = format!("{} (= {})", self.deb_name, self.deb_version);
+        dbgsym.recommends = None;
+        dbgsym.conflicts = None;
+        dbgsym.breaks = None;
+        dbgsym.replaces = None;
+        dbgsym.provides = None;
+        dbgsym.section = Some("debug".to_owned());
+        dbgsym.priority = "optional".to_owned();
+        dbgsym.description = format!("debug symbols for {}", self.deb_name);
+        dbgsym.extended_description = None;
+        dbgsym.auto_built_package = Some("debug-symbols".to_owned());
+        dbgsym.assets = Assets::with_resolved_assets(assets);
+        dbgsym.shlibs = Vec::new();
+        dbgsym.dependency_license_notices = Vec::new();
+        Some(dbgsym)
+    }
+
+    /// Builds a `lib<name>-dev` companion `Config` carrying a generated `pkg-config` file and the
+    /// conventional unversioned `libfoo.so -> libfoo.so.1` symlink for this crate's cdylib(s), so
+    /// C/C++ consumers can `pkg-config --libs <name>` and link against `-lfoo` directly. Returns
+    /// `None` if this crate doesn't ship a shared library.
+    pub fn split_dev_package(&self) -> Option<Config> {
+        let libs: Vec<&Asset> = self.built_binaries().into_iter().filter(|a| a.is_dynamic_library()).collect();
+        if libs.is_empty() {
+            return None;
+        }
+
+        let multiarch_triple = crate::debian_triple(self.target.as_deref().unwrap_or(crate::DEFAULT_TARGET));
+        let version = self.deb_version.split('-').next().unwrap_or(&self.deb_version);
+        let pkgconfig = format!(
+            "prefix=/usr\nlibdir=${{prefix}}/lib/{multiarch}\n\nName: {name}\nDescription: {desc}\nVersion: {version}\nLibs: -L${{libdir}} -l{name}\n",
+            multiarch = multiarch_triple,
+            name = self.name,
+            desc = self.description,
+            version = version,
+        );
+        let pkgconfig_asset = Asset::new(
+            AssetSource::Data(pkgconfig.into_bytes()),
+            Path::new("usr/lib").join(&multiarch_triple).join("pkgconfig").join(format!("{}.pc", self.name)),
+            0o644,
+            false,
+        );
+
+        // The runtime package only ships the SONAME-versioned file (e.g. libfoo.so.1); the `-dev`
+        // package gets the unversioned symlink that lets `-lfoo` find it at link time.
+        let lib_name = format!("{}{}{}", DLL_PREFIX, self.name, DLL_SUFFIX);
+        let dev_symlinks = libs.iter().filter_map(|lib| {
+            let soname = lib.target_path.file_name()?.to_str()?;
+            if soname == lib_name {
+                return None;
+            }
+            let dir = lib.target_path.parent()?;
+            Some(Asset::new(AssetSource::Symlink(PathBuf::from(soname)), dir.join(&lib_name), 0o777, false))
+        });
+
+        let mut dev = self.clone();
+        dev.depends = format!("{} (= {})", self.deb_name, self.deb_version);
+        dev.deb_name = format!("lib{}-dev", self.name);
+        dev.recommends = None;
+        dev.conflicts = None;
+        dev.breaks = None;
+        dev.replaces = None;
+        dev.provides = None;
+        dev.section = Some("libdevel".to_owned());
+        dev.priority = "optional".to_owned();
+        dev.description = format!("development files for {}", self.name);
+        dev.extended_description = None;
+        dev.auto_built_package = None;
+        dev.assets = Assets::with_resolved_assets(std::iter::once(pkgconfig_asset).chain(dev_symlinks).collect());
+        dev.shlibs = Vec::new();
+        dev.dependency_license_notices = Vec::new();
+        Some(dev)
+    }
+
     fn add_changelog_asset(&mut self) -> CDResult<()> {
         // The file is autogenerated later
         if self.changelog.is_some() {
             if let Some(changelog_file) = crate::data::generate_changelog_asset(self)? {
+                let target_path = Path::new("usr/share/doc")
+                    .join(&self.deb_name)
+                    .join(changelog_file.member_name("changelog"));
                 self.assets.resolved.push(Asset::new(
-                    AssetSource::Data(changelog_file),
-                    Path::new("usr/share/doc")
-                        .join(&self.deb_name)
-                        .join("changelog.gz"),
+                    AssetSource::Data(changelog_file.to_vec()),
+                    target_path,
                     0o644,
                     false,
                 ));
@@ -410,12 +798,63 @@ impl Config {
         Ok(())
     }
 
+    /// Repoints each built-binary/cdylib asset's `source` at the real path [`crate::cargo_build`]
+    /// reported `cargo` produced for it, by matching on filename, instead of leaving it at the
+    /// `target/<triple>/release/<name>` path [`Config::path_in_build`] guessed when assets were
+    /// first resolved (before the build actually ran). This is what lets [`crate::strip_binaries`]
+    /// and friends operate on the file cargo really wrote instead of a reconstructed guess that's
+    /// wrong for custom `--target-dir`s or differently-named build outputs.
+    ///
+    /// Also re-derives each cdylib's SONAME-versioned target path and `shlibs` entry now that the
+    /// real file exists to read `DT_SONAME` from: [`Cargo::take_assets`] can only run before the
+    /// build, when `read_soname` would either find nothing or, worse, read a stale SONAME left
+    /// over from a previous build.
+    pub(crate) fn apply_build_artifacts(&mut self, artifacts: &[crate::BuiltArtifact]) {
+        let mut shlibs = Vec::new();
+        for asset in &mut self.assets.resolved {
+            if !asset.is_built {
+                continue;
+            }
+            let file_name = match asset.source.path().and_then(|p| p.file_name()) {
+                Some(file_name) => file_name.to_owned(),
+                None => continue,
+            };
+            let artifact = match artifacts.iter().find(|a| a.path.file_name() == Some(file_name.as_os_str())) {
+                Some(artifact) => artifact,
+                None => continue,
+            };
+            asset.source = AssetSource::Path(artifact.path.clone());
+
+            if !asset.is_dynamic_library() {
+                continue;
+            }
+            let lib_name = match asset.target_path.file_name().and_then(|f| f.to_str()) {
+                Some(lib_name) => lib_name.to_owned(),
+                None => continue,
+            };
+            if let Some(soname) = read_soname(&artifact.path) {
+                if let Some(major) = soname.strip_prefix(&format!("{}.", lib_name)) {
+                    let lib_base = lib_name.trim_end_matches(DLL_SUFFIX);
+                    shlibs.push(format!("{} {} {} (>= {})", lib_base, major, self.deb_name, self.deb_version));
+                }
+                if let Some(dir) = asset.target_path.parent().map(Path::to_path_buf) {
+                    // Install the real file under its SONAME (e.g. libfoo.so.1); the unversioned
+                    // `libfoo.so -> libfoo.so.1` symlink is a development convenience, not
+                    // something runtime users need, so it ships with the `-dev` companion package
+                    // instead (see `split_dev_package`).
+                    asset.target_path = dir.join(&soname);
+                }
+            }
+        }
+        self.shlibs.extend(shlibs);
+    }
+
     /// Executables AND dynamic libraries
     fn all_binaries(&self) -> Vec<&AssetSource> {
         self.binaries(false).iter().map(|asset| &asset.source).collect()
     }
 
-    /// Executables AND dynamic libraries, but only in `target/release`
+    /// Executables AND dynamic libraries, but only ones built for `build_profile`
     pub(crate) fn built_binaries(&self) -> Vec<&Asset> {
         self.binaries(true)
     }
@@ -459,8 +898,48 @@ impl Config {
         None
     }
 
+    /// Records the git commit hash and dirty state of `manifest_dir` as a `vcs_info.json`
+    /// asset, mirroring cargo's `.cargo_vcs_info.json`. Best-effort: anything other than a
+    /// clean `git rev-parse`/`git status` just produces a warning, since not every source
+    /// tree is a supported VCS checkout.
+    fn add_vcs_info_asset(&mut self, listener: &dyn Listener) {
+        let info = match self.repository_type() {
+            Some("Git") => match collect_git_info(&self.manifest_dir) {
+                Ok(info) => info,
+                Err(err) => {
+                    listener.warning(format!("unable to capture VCS provenance: {}", err));
+                    return;
+                },
+            },
+            Some(other) => {
+                listener.warning(format!("VCS provenance is only supported for Git, not {}", other));
+                return;
+            },
+            None => return,
+        };
+
+        let json = format!("{{\n  \"git\": {{\n    \"sha1\": \"{}\",\n    \"dirty\": {}\n  }}\n}}\n", info.commit, info.dirty);
+        self.assets.resolved.push(Asset::new(
+            AssetSource::Data(json.into_bytes()),
+            Path::new("usr/share/doc").join(&self.deb_name).join("vcs_info.json"),
+            0o644,
+            false,
+        ));
+        self.vcs_info = Some(info);
+    }
+
     pub(crate) fn path_in_build<P: AsRef<Path>>(&self, rel_path: P) -> PathBuf {
-        self.target_dir.join("release").join(rel_path)
+        self.target_dir.join(self.build_profile_dir_name()).join(rel_path)
+    }
+
+    /// Cargo's on-disk directory name for `build_profile`: the `dev` profile builds into
+    /// `target/debug` for historical reasons; every other profile (including custom ones) builds
+    /// into `target/<profile-name>`.
+    pub(crate) fn build_profile_dir_name(&self) -> &str {
+        match self.build_profile.as_str() {
+            "dev" => "debug",
+            other => other,
+        }
     }
 
     pub(crate) fn path_in_workspace<P: AsRef<Path>>(&self, rel_path: P) -> PathBuf {
@@ -472,6 +951,15 @@ impl Config {
         self.target_dir.join("debian")
     }
 
+    /// The output `.deb`'s file name, e.g. `cargo-deb_1.2.3_amd64.deb` (or with a different
+    /// separator if `deb_name_separator` overrides the default `_`).
+    pub(crate) fn deb_output_filename(&self) -> String {
+        const DEFAULT_SEPARATOR: char = '_';
+        format!("{}{sep}{}{sep}{}.deb", self.deb_name, self.deb_version, self.architecture,
+            sep = self.deb_name_separator.unwrap_or(DEFAULT_SEPARATOR)
+        )
+    }
+
     /// Save final .deb here
     pub(crate) fn deb_output_path(&self, filename: &str) -> PathBuf {
         if let Some(ref path_str) = self.deb_output_path {
@@ -495,6 +983,8 @@ impl Config {
 struct Cargo {
     pub package: cargo_toml::Package<CargoPackageMetadata>,
     pub profile: Option<cargo_toml::Profiles>,
+    #[serde(default)]
+    pub features: HashMap<String, Vec<String>>,
 }
 
 impl Cargo {
@@ -505,7 +995,8 @@ impl Cargo {
     ///
     fn into_config(
         mut self,
-        root_package: &CargoMetadataPackage,
+        root_package: &cargo_metadata::Package,
+        metadata: &cargo_metadata::Metadata,
         manifest_dir: &Path,
         deb_output_path: Option<String>,
         target_dir: &Path,
@@ -513,6 +1004,7 @@ impl Cargo {
         variant: Option<&str>,
         deb_version: Option<String>,
         listener: &dyn Listener,
+        build_profile: &str,
     ) -> CDResult<Config> {
         // Cargo cross-compiles to a dir
         let target_dir = if let Some(target) = target {
@@ -546,11 +1038,13 @@ impl Cargo {
         let (license_file, license_file_skip_lines) = self.license_file(deb.license_file.as_ref())?;
         let readme = self.package.readme.as_ref();
         self.check_config(manifest_dir, readme, &deb, listener);
+        let auto_depends = resolve_crate_dependencies(metadata, &root_package.id, deb.dependencies.take().as_ref());
         let mut config = Config {
             manifest_dir: manifest_dir.to_owned(),
             deb_output_path,
             target: target.map(|t| t.to_string()),
             target_dir,
+            build_profile: build_profile.to_owned(),
             name: self.package.name.clone(),
             deb_name: deb.name.take().unwrap_or(self.package.name.clone()),
             deb_version: deb_version.unwrap_or(self.version_string(deb.revision)),
@@ -573,20 +1067,66 @@ impl Cargo {
                     .ok_or("The package must have a maintainer or authors property")?.to_owned())
             })?,
             depends: deb.depends.take().unwrap_or("$auto".to_owned()),
+            auto_depends,
             conflicts: deb.conflicts.take(),
             breaks: deb.breaks.take(),
             replaces: deb.replaces.take(),
             provides: deb.provides.take(),
+            recommends: deb.recommends.take(),
             section: deb.section.take(),
             priority: deb.priority.take().unwrap_or("optional".to_owned()),
-            architecture: get_arch(target.unwrap_or(crate::DEFAULT_TARGET)).to_owned(),
+            architecture: get_arch(target.unwrap_or(crate::DEFAULT_TARGET)),
             conf_files: deb.conf_files.map(|x| x.iter().fold(String::new(), |a, b| a + b + "\n")),
             assets: Assets::new(),
             changelog: deb.changelog.take(),
             maintainer_scripts: deb.maintainer_scripts.map(PathBuf::from),
+            maintainer_scripts_rerun_if_changed: deb.maintainer_scripts_rerun_if_changed.take().unwrap_or_default()
+                .into_iter().map(PathBuf::from).collect(),
+            systemd_units: deb.systemd_units.take().map(|s| SystemdUnitsConfig {
+                unit_name: s.unit_name,
+                enable: s.enable.unwrap_or(true),
+                start: s.start.unwrap_or(true),
+                restart_after_upgrade: s.restart_after_upgrade.unwrap_or(true),
+                no_stop_on_upgrade: s.no_stop_on_upgrade.unwrap_or(false),
+                exclude: s.exclude.unwrap_or_default(),
+                only_units: s.only_units,
+                compat: s.compat.unwrap_or(13),
+                no_static_unit_warnings: s.no_static_unit_warnings.unwrap_or(false),
+                unit_overrides: s.units.unwrap_or_default().into_iter()
+                    .map(|(name, o)| (name, UnitOverride { enable: o.enable }))
+                    .collect(),
+                no_tmpfiles: s.no_tmpfiles.unwrap_or(false),
+                no_sysusers: s.no_sysusers.unwrap_or(false),
+            }),
+            autopkgtest: deb.autopkgtest.take().map(|a| AutopkgtestConfig {
+                test_commands: a.test_commands.unwrap_or_default(),
+                triggers: a.triggers.unwrap_or_default(),
+                skip_systemd_smoke_test: a.skip_systemd_smoke_test.unwrap_or(false),
+            }),
             features: deb.features.take().unwrap_or(vec![]),
             default_features: deb.default_features.unwrap_or(true),
             separate_debug_symbols: deb.separate_debug_symbols.unwrap_or(false),
+            fix_rpath: deb.fix_rpath.unwrap_or(true),
+            auto_built_package: None,
+            asset_excludes: compile_patterns(deb.exclude.take())?,
+            asset_includes: compile_patterns(deb.include.take())?,
+            compress_doc_exclude: compile_patterns(deb.compress_doc_exclude.take())?,
+            vcs_info: None,
+            shlibs: Vec::new(),
+            separate_dep_licenses: deb.separate_dep_licenses.unwrap_or(false),
+            dependency_license_notices: if deb.separate_dep_licenses.unwrap_or(false) {
+                collect_dependency_license_notices(metadata, &root_package.id)
+            } else {
+                Vec::new()
+            },
+            compress_type: crate::compress::Compression::default(),
+            compress_level: None,
+            xz_compression: crate::compress::XzCompressionSettings {
+                dict_size: deb.xz_dict_size.take(),
+                threads: deb.xz_threads.take(),
+            },
+            deterministic: false,
+            hardlink_dedup: deb.hardlink_dedup.unwrap_or(false),
             strip: self.profile.as_ref().and_then(|p|p.release.as_ref())
                 .and_then(|r| r.debug.as_ref())
                 .map_or(true, |debug| match *debug {
@@ -596,13 +1136,16 @@ impl Cargo {
                 }),
             _use_constructor_to_make_this_struct_: (),
         };
-        let assets = self.take_assets(&config, deb.assets.take(), &root_package.targets, readme)?;
+        let (assets, shlibs) = self.take_assets(&config, deb.assets.take(), &root_package.targets, readme)?;
         if assets.is_empty() {
             Err("No binaries or cdylibs found. The package is empty. Please specify some assets to package in Cargo.toml")?;
         }
         config.assets = assets;
-        config.add_copyright_asset()?;
+        config.shlibs = shlibs;
+        self.merge_active_features(&mut config, deb.feature.take())?;
+        config.add_copyright_asset(listener)?;
         config.add_changelog_asset()?;
+        config.add_vcs_info_asset(listener);
 
         Ok(config)
     }
@@ -652,53 +1195,70 @@ impl Cargo {
         }
     }
 
-    fn take_assets(&self, options: &Config, assets: Option<Vec<Vec<String>>>, targets: &[CargoMetadataTarget], readme: Option<&String>) -> CDResult<Assets> {
+    fn parse_asset_line(&self, options: &Config, mut asset_line: Vec<String>) -> CDResult<UnresolvedAsset> {
+        let mut asset_parts = asset_line.drain(..);
+        let source_path = PathBuf::from(asset_parts.next()
+            .ok_or("missing path (first array entry) for asset in Cargo.toml")?);
+        let build_dir_prefix = Path::new("target").join(options.build_profile_dir_name());
+        let (is_built, source_path) = if let Ok(rel_path) = source_path.strip_prefix(&build_dir_prefix) {
+            (true, options.path_in_build(rel_path))
+        } else {
+            (false, options.path_in_workspace(&source_path))
+        };
+        let target_path = PathBuf::from(asset_parts.next().ok_or("missing target (second array entry) for asset in Cargo.toml")?);
+        let chmod = u32::from_str_radix(&asset_parts.next().ok_or("missing chmod (third array entry) for asset in Cargo.toml")?, 8)
+            .map_err(|e| CargoDebError::NumParse("unable to parse chmod argument", e))?;
+        // Optional fourth array entry: "user:group" ownership, e.g. for a service account's
+        // config/state directory. Defaults to root:root when omitted.
+        let owner = asset_parts.next().map(|spec| parse_asset_owner(&spec)).transpose()?;
+        // Optional fifth array entry: Linux capabilities, e.g. "cap_net_bind_service+ep", so a
+        // binary can bind privileged ports without a postinst `setcap` call.
+        let capabilities = asset_parts.next().map(|spec| parse_asset_capabilities(&spec)).transpose()?;
+
+        Ok(UnresolvedAsset {
+            source_path,
+            target_path,
+            chmod,
+            is_built,
+            owner,
+            capabilities,
+        })
+    }
+
+    fn take_assets(&self, options: &Config, assets: Option<Vec<Vec<String>>>, targets: &[cargo_metadata::Target], readme: Option<&String>) -> CDResult<(Assets, Vec<String>)> {
         Ok(if let Some(assets) = assets {
             // Treat all explicit assets as unresolved until after the build step
             let mut unresolved_assets = vec![];
-            for mut asset_line in assets {
-                let mut asset_parts = asset_line.drain(..);
-                let source_path = PathBuf::from(asset_parts.next()
-                    .ok_or("missing path (first array entry) for asset in Cargo.toml")?);
-                let (is_built, source_path) = if let Ok(rel_path) = source_path.strip_prefix("target/release") {
-                    (true, options.path_in_build(rel_path))
-                } else {
-                    (false, options.path_in_workspace(&source_path))
-                };
-                let target_path = PathBuf::from(asset_parts.next().ok_or("missing target (second array entry) for asset in Cargo.toml")?);
-                let chmod = u32::from_str_radix(&asset_parts.next().ok_or("missing chmod (third array entry) for asset in Cargo.toml")?, 8)
-                    .map_err(|e| CargoDebError::NumParse("unable to parse chmod argument", e))?;
-
-                unresolved_assets.push(UnresolvedAsset {
-                    source_path,
-                    target_path,
-                    chmod,
-                    is_built,
-                })
+            for asset_line in assets {
+                unresolved_assets.push(self.parse_asset_line(options, asset_line)?);
             }
-            Assets::with_unresolved_assets(unresolved_assets)
+            (Assets::with_unresolved_assets(unresolved_assets), Vec::new())
         } else {
             let mut implied_assets: Vec<_> = targets
                 .iter()
-                .filter_map(|t| {
+                .flat_map(|t| {
                     if t.crate_types.iter().any(|ty| ty == "bin") && t.kind.iter().any(|k| k == "bin") {
-                        Some(Asset::new(
+                        vec![Asset::new(
                             AssetSource::Path(options.path_in_build(&t.name)),
                             Path::new("usr/bin").join(&t.name),
                             0o755,
                             true,
-                        ))
+                        )]
                     } else if t.crate_types.iter().any(|ty| ty == "cdylib") && t.kind.iter().any(|k| k == "cdylib") {
                         // FIXME: std has constants for the host arch, but not for cross-compilation
                         let lib_name = format!("{}{}{}", DLL_PREFIX, t.name, DLL_SUFFIX);
-                        Some(Asset::new(
-                            AssetSource::Path(options.path_in_build(&lib_name)),
-                            Path::new("usr/lib").join(lib_name),
-                            0o644,
-                            true,
-                        ))
+                        // Debian multiarch expects shared libraries under a per-triple directory,
+                        // e.g. /usr/lib/x86_64-linux-gnu/libfoo.so, so ldconfig/the linker find them.
+                        let multiarch_triple = crate::debian_triple(options.target.as_deref().unwrap_or(crate::DEFAULT_TARGET));
+                        let lib_dir = Path::new("usr/lib").join(multiarch_triple);
+                        let built_path = options.path_in_build(&lib_name);
+                        // The real artifact doesn't exist yet at this point (the build hasn't run),
+                        // so its DT_SONAME can't be read here; target this at the unversioned name
+                        // for now and let `apply_build_artifacts` re-derive the SONAME-versioned
+                        // path and `shlibs` entry once the real file exists.
+                        vec![Asset::new(AssetSource::Path(built_path), lib_dir.join(lib_name), 0o644, true)]
                     } else {
-                        None
+                        vec![]
                     }
                 })
                 .collect();
@@ -711,10 +1271,46 @@ impl Cargo {
                     false,
                 ));
             }
-            Assets::with_resolved_assets(implied_assets)
+            // `shlibs` is derived from the real artifact's SONAME in `apply_build_artifacts`,
+            // once the build has actually produced it.
+            (Assets::with_resolved_assets(implied_assets), Vec::new())
         })
     }
 
+    /// Merges assets and dependencies from `[package.metadata.deb.feature.<name>]` tables
+    /// whose Cargo feature `name` is enabled, either explicitly via `config.features` or
+    /// implicitly via `default-features` and the crate's `[features] default = [...]`.
+    fn merge_active_features(&self, config: &mut Config, features_table: Option<HashMap<String, CargoDebFeature>>) -> CDResult<()> {
+        let features_table = match features_table {
+            Some(table) => table,
+            None => return Ok(()),
+        };
+
+        let mut active_features: HashSet<&str> = config.features.iter().map(|s| s.as_str()).collect();
+        if config.default_features {
+            if let Some(defaults) = self.features.get("default") {
+                active_features.extend(defaults.iter().map(|s| s.as_str()));
+            }
+        }
+
+        for (name, feat) in features_table {
+            if !active_features.contains(name.as_str()) {
+                continue;
+            }
+            if let Some(asset_lines) = feat.assets {
+                for asset_line in asset_lines {
+                    config.assets.unresolved.push(self.parse_asset_line(config, asset_line)?);
+                }
+            }
+            if let Some(extra) = feat.depends {
+                config.depends = format!("{}, {}", config.depends, extra);
+            }
+            config.recommends = merge_dep_list(config.recommends.take(), feat.recommends);
+            config.provides = merge_dep_list(config.provides.take(), feat.provides);
+        }
+        Ok(())
+    }
+
     fn version_string(&self, revision: Option<String>) -> String {
         if let Some(revision) = revision {
             format!("{}-{}", self.package.version, revision)
@@ -742,6 +1338,7 @@ struct CargoDeb {
     pub breaks: Option<String>,
     pub replaces: Option<String>,
     pub provides: Option<String>,
+    pub recommends: Option<String>,
     pub extended_description: Option<String>,
     pub section: Option<String>,
     pub priority: Option<String>,
@@ -749,10 +1346,106 @@ struct CargoDeb {
     pub conf_files: Option<Vec<String>>,
     pub assets: Option<Vec<Vec<String>>>,
     pub maintainer_scripts: Option<String>,
+    /// Extra files whose content invalidates the fingerprint cached for generated maintainer
+    /// scripts, so hand-maintained fragments they depend on also trigger regeneration.
+    pub maintainer_scripts_rerun_if_changed: Option<Vec<String>>,
     pub features: Option<Vec<String>>,
     pub default_features: Option<bool>,
     pub separate_debug_symbols: Option<bool>,
+    /// Strip build-host `DT_RPATH`/`DT_RUNPATH` entries from packaged ELF binaries. Defaults to `true`.
+    pub fix_rpath: Option<bool>,
     pub variants: Option<HashMap<String, CargoDeb>>,
+    /// Glob patterns; resolved assets matching any of these are dropped.
+    pub exclude: Option<Vec<String>>,
+    /// Glob patterns; when non-empty, only resolved assets matching one of these are kept.
+    pub include: Option<Vec<String>>,
+    /// Per-Cargo-feature assets and dependencies, merged in when the feature is enabled.
+    pub feature: Option<HashMap<String, CargoDebFeature>>,
+    /// Maps upstream crate names to the Debian packages that provide them, e.g.
+    /// `openssl-sys = "libssl-dev"`. Only consulted when `depends` contains `$auto`: crates
+    /// in the resolved normal-dependency graph that have an entry here are added to `Depends`.
+    pub dependencies: Option<HashMap<String, String>>,
+    /// When `true`, `copyright` gets one extra DEP-5 paragraph per crate in the resolved
+    /// normal-dependency graph, carrying its SPDX license and any `LICENSE`/`NOTICE`/`AUTHORS`
+    /// files found in its source directory. Off by default to avoid bloating small packages.
+    pub separate_dep_licenses: Option<bool>,
+    /// Glob patterns (matched against the asset's archive target path); documentation assets
+    /// that would otherwise be compressed by `compress_documentation` are left alone instead.
+    pub compress_doc_exclude: Option<Vec<String>>,
+    /// `dh_installsystemd`-equivalent autoscript generation settings for this package's
+    /// installed systemd units. Presence of the table (even empty) opts the package in.
+    pub systemd_units: Option<CargoDebSystemdUnits>,
+    /// Autopkgtest (`debian/tests/control`-equivalent) generation settings. Presence of the
+    /// table (even empty) opts the package in.
+    pub autopkgtest: Option<CargoDebAutopkgtest>,
+    /// Explicit xz (LZMA2) dictionary/window size in bytes, e.g. `67108864` for 64 MiB.
+    /// Consulted only when compressing with xz.
+    pub xz_dict_size: Option<u32>,
+    /// Caps the number of threads xz compression may use; unset uses every available core.
+    pub xz_threads: Option<u32>,
+    /// When `true`, assets whose content is byte-for-byte identical to one already archived are
+    /// stored as a tar hardlink to the first copy instead of being re-compressed. Off by default,
+    /// since it relies on the target's `dpkg` being able to unpack hardlinked members.
+    pub hardlink_dedup: Option<bool>,
+}
+
+/// Raw `[package.metadata.deb.systemd-units]` table, resolved into a [`SystemdUnitsConfig`] by
+/// [`Cargo::into_config`].
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CargoDebSystemdUnits {
+    pub unit_name: Option<String>,
+    pub enable: Option<bool>,
+    pub start: Option<bool>,
+    pub restart_after_upgrade: Option<bool>,
+    pub no_stop_on_upgrade: Option<bool>,
+    /// Unit (base) names to exclude from maintainer-script generation (`dh_installsystemd -X`).
+    pub exclude: Option<Vec<String>>,
+    /// Restrict maintainer-script generation to exactly these unit (base) names.
+    pub only_units: Option<Vec<String>>,
+    /// debhelper compat level to emulate; defaults to 13 (current). See [`SystemdUnitsConfig::compat`].
+    pub compat: Option<u32>,
+    /// Suppress the warning emitted for static (no `[Install]` section) units. See
+    /// [`SystemdUnitsConfig::no_static_unit_warnings`].
+    pub no_static_unit_warnings: Option<bool>,
+    /// Per-unit override table, keyed by unit (base) name, e.g.
+    /// `[package.metadata.deb.systemd-units.units."myunit.service"]`.
+    pub units: Option<HashMap<String, CargoDebSystemdUnitOverride>>,
+    /// See [`SystemdUnitsConfig::no_tmpfiles`].
+    pub no_tmpfiles: Option<bool>,
+    /// See [`SystemdUnitsConfig::no_sysusers`].
+    pub no_sysusers: Option<bool>,
+}
+
+/// Raw form of a single entry in [`CargoDebSystemdUnits::units`].
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CargoDebSystemdUnitOverride {
+    pub enable: Option<bool>,
+}
+
+/// Raw `[package.metadata.deb.autopkgtest]` table, resolved into an [`AutopkgtestConfig`] by
+/// [`Cargo::into_config`].
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CargoDebAutopkgtest {
+    /// See [`AutopkgtestConfig::test_commands`].
+    pub test_commands: Option<Vec<String>>,
+    /// See [`AutopkgtestConfig::triggers`].
+    pub triggers: Option<Vec<String>>,
+    /// See [`AutopkgtestConfig::skip_systemd_smoke_test`].
+    pub skip_systemd_smoke_test: Option<bool>,
+}
+
+/// Extra assets and dependencies contributed by `[package.metadata.deb.feature.<name>]`
+/// when the Cargo feature `<name>` is enabled.
+#[derive(Clone, Debug, Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct CargoDebFeature {
+    pub assets: Option<Vec<Vec<String>>>,
+    pub depends: Option<String>,
+    pub recommends: Option<String>,
+    pub provides: Option<String>,
 }
 
 impl CargoDeb {
@@ -768,6 +1461,7 @@ impl CargoDeb {
             breaks: self.breaks.or(parent.breaks),
             replaces: self.replaces.or(parent.replaces),
             provides: self.provides.or(parent.provides),
+            recommends: self.recommends.or(parent.recommends),
             extended_description: self.extended_description.or(parent.extended_description),
             section: self.section.or(parent.section),
             priority: self.priority.or(parent.priority),
@@ -775,63 +1469,242 @@ impl CargoDeb {
             conf_files: self.conf_files.or(parent.conf_files),
             assets: self.assets.or(parent.assets),
             maintainer_scripts: self.maintainer_scripts.or(parent.maintainer_scripts),
+            maintainer_scripts_rerun_if_changed: self.maintainer_scripts_rerun_if_changed.or(parent.maintainer_scripts_rerun_if_changed),
             features: self.features.or(parent.features),
             default_features: self.default_features.or(parent.default_features),
             separate_debug_symbols: self.separate_debug_symbols.or(parent.separate_debug_symbols),
+            fix_rpath: self.fix_rpath.or(parent.fix_rpath),
             variants: self.variants.or(parent.variants),
+            exclude: self.exclude.or(parent.exclude),
+            include: self.include.or(parent.include),
+            feature: self.feature.or(parent.feature),
+            dependencies: self.dependencies.or(parent.dependencies),
+            separate_dep_licenses: self.separate_dep_licenses.or(parent.separate_dep_licenses),
+            compress_doc_exclude: self.compress_doc_exclude.or(parent.compress_doc_exclude),
+            systemd_units: self.systemd_units.or(parent.systemd_units),
+            autopkgtest: self.autopkgtest.or(parent.autopkgtest),
+            xz_dict_size: self.xz_dict_size.or(parent.xz_dict_size),
+            xz_threads: self.xz_threads.or(parent.xz_threads),
+            hardlink_dedup: self.hardlink_dedup.or(parent.hardlink_dedup),
         }
     }
 }
 
-#[derive(Deserialize)]
-struct CargoMetadata {
-    packages: Vec<CargoMetadataPackage>,
-    resolve: CargoMetadataResolve,
-    #[serde(default)]
-    workspace_members: Vec<String>,
-    target_directory: String,
+fn merge_dep_list(existing: Option<String>, extra: Option<String>) -> Option<String> {
+    match (existing, extra) {
+        (Some(existing), Some(extra)) => Some(format!("{}, {}", existing, extra)),
+        (existing, extra) => existing.or(extra),
+    }
 }
 
-#[derive(Deserialize)]
-struct CargoMetadataResolve {
-    root: Option<String>,
+fn compile_patterns(patterns: Option<Vec<String>>) -> CDResult<Vec<glob::Pattern>> {
+    patterns.unwrap_or_default().iter()
+        .map(|pat| Ok(glob::Pattern::new(pat)?))
+        .collect()
 }
 
-#[derive(Deserialize)]
-struct CargoMetadataPackage {
-    pub id: String,
-    pub name: String,
-    pub targets: Vec<CargoMetadataTarget>,
-    pub manifest_path: String,
+/// Runs `cargo metadata` for the workspace containing `manifest_path`.
+fn cargo_metadata(manifest_path: &Path) -> CDResult<cargo_metadata::Metadata> {
+    cargo_metadata::MetadataCommand::new()
+        .manifest_path(manifest_path)
+        .exec()
+        .map_err(CargoDebError::from)
 }
 
-#[derive(Deserialize)]
-struct CargoMetadataTarget {
+/// One dependency crate's license-obligation paragraph for the Debian `copyright` file,
+/// collected by [`collect_dependency_license_notices`] when `separate_dep_licenses` is set.
+#[derive(Debug, Clone)]
+pub(crate) struct DependencyLicenseNotice {
     pub name: String,
-    pub kind: Vec<String>,
-    pub crate_types: Vec<String>,
+    pub version: String,
+    pub license: Option<String>,
+    /// Verbatim contents of any `LICENSE*`/`COPYING*`/`NOTICE*`/`AUTHORS*` files found in the
+    /// crate's source directory, each tagged with the file name it came from.
+    pub texts: Vec<(String, String)>,
 }
 
-/// Returns the path of the `Cargo.toml` that we want to build.
-fn cargo_metadata(manifest_path: &Path) -> CDResult<CargoMetadata> {
-    let mut cmd = Command::new("cargo");
-    cmd.arg("metadata");
-    cmd.arg("--format-version=1");
-    cmd.arg(format!("--manifest-path={}", manifest_path.display()));
+/// Walks the resolved *normal* dependency closure of `root_id` (skipping `root_id` itself) and
+/// collects a [`DependencyLicenseNotice`] for every crate found, scanning each crate's source
+/// directory for license/notice/authors files. Returns an empty list when there's no resolve
+/// graph (e.g. `cargo metadata` ran with `--no-deps`).
+fn collect_dependency_license_notices(metadata: &cargo_metadata::Metadata, root_id: &cargo_metadata::PackageId) -> Vec<DependencyLicenseNotice> {
+    let resolve = match metadata.resolve.as_ref() {
+        Some(r) => r,
+        None => return Vec::new(),
+    };
+
+    let mut seen = HashSet::new();
+    let mut stack = vec![root_id.clone()];
+    let mut notices = Vec::new();
+    while let Some(id) = stack.pop() {
+        if !seen.insert(id.clone()) {
+            continue;
+        }
+        let node = match resolve.nodes.iter().find(|n| n.id == id) {
+            Some(n) => n,
+            None => continue,
+        };
+        for dep in &node.deps {
+            let is_normal = dep.dep_kinds.iter().any(|k| k.kind == cargo_metadata::DependencyKind::Normal);
+            if is_normal {
+                stack.push(dep.pkg.clone());
+            }
+        }
+        if id == *root_id {
+            continue;
+        }
+        if let Some(pkg) = metadata.packages.iter().find(|p| p.id == id) {
+            let texts = pkg.manifest_path.parent()
+                .map(|dir| find_license_texts(dir.as_std_path()))
+                .unwrap_or_default();
+            notices.push(DependencyLicenseNotice {
+                name: pkg.name.clone(),
+                version: pkg.version.to_string(),
+                license: pkg.license.clone(),
+                texts,
+            });
+        }
+    }
+    notices.sort_by(|a, b| a.name.cmp(&b.name));
+    notices
+}
 
-    let output = cmd.output()
-        .map_err(|e| CargoDebError::CommandFailed(e, "cargo (is it in your PATH?)"))?;
+/// Scans `dir` (non-recursively) for `LICENSE*`, `COPYING*`, `NOTICE*` and `AUTHORS*` files and
+/// returns their (file name, contents) pairs, skipping anything that isn't valid UTF-8.
+fn find_license_texts(dir: &Path) -> Vec<(String, String)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut found: Vec<_> = entries.flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let upper = name.to_uppercase();
+            if upper.starts_with("LICENSE") || upper.starts_with("COPYING") || upper.starts_with("NOTICE") || upper.starts_with("AUTHORS") {
+                fs::read_to_string(entry.path()).ok().map(|text| (name, text))
+            } else {
+                None
+            }
+        })
+        .collect();
+    found.sort();
+    found
+}
+
+/// Walks the resolved *normal* dependency closure of `root_id` and maps any crate name found
+/// in `dependency_map` (from `[package.metadata.deb.dependencies]`) to its Debian package name.
+/// Returns an empty list when there's no dependency map or no `cargo metadata` resolve graph.
+fn resolve_crate_dependencies(metadata: &cargo_metadata::Metadata, root_id: &cargo_metadata::PackageId, dependency_map: Option<&HashMap<String, String>>) -> Vec<String> {
+    let dependency_map = match dependency_map {
+        Some(m) if !m.is_empty() => m,
+        _ => return Vec::new(),
+    };
+    let resolve = match metadata.resolve.as_ref() {
+        Some(r) => r,
+        None => return Vec::new(),
+    };
+
+    let mut seen = HashSet::new();
+    let mut stack = vec![root_id.clone()];
+    let mut deps = HashSet::new();
+    while let Some(id) = stack.pop() {
+        if !seen.insert(id.clone()) {
+            continue;
+        }
+        let node = match resolve.nodes.iter().find(|n| n.id == id) {
+            Some(n) => n,
+            None => continue,
+        };
+        for dep in &node.deps {
+            let is_normal = dep.dep_kinds.iter().any(|k| k.kind == cargo_metadata::DependencyKind::Normal);
+            if !is_normal {
+                continue;
+            }
+            if let Some(pkg) = metadata.packages.iter().find(|p| p.id == dep.pkg) {
+                if let Some(deb_pkg) = dependency_map.get(&pkg.name) {
+                    deps.insert(deb_pkg.clone());
+                }
+            }
+            stack.push(dep.pkg.clone());
+        }
+    }
+    deps.into_iter().collect()
+}
+
+/// Computes the `/usr/lib/debug/.build-id/xx/yyyy....debug` target path that `dbgsym`
+/// packages and debuggers (via the `.gnu_debuglink`/build-id lookup convention) expect.
+/// Returns `None` when the binary carries no ELF `NT_GNU_BUILD_ID` note, e.g. it was linked
+/// with `-Wl,--build-id=none`.
+fn build_id_debug_target(debug_source: &Path) -> Option<PathBuf> {
+    let id = read_build_id(debug_source)?;
+    if id.len() < 3 {
+        return None;
+    }
+    let (prefix, rest) = id.split_at(2);
+    Some(Path::new("usr/lib/debug/.build-id").join(prefix).join(format!("{}.debug", rest)))
+}
+
+/// Shells out to `readelf -n` to read the hex `NT_GNU_BUILD_ID` note from an ELF file.
+fn read_build_id(path: &Path) -> Option<String> {
+    let output = Command::new("readelf").arg("-n").arg(path).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines()
+        .find_map(|line| line.split_once("Build ID: "))
+        .map(|(_, id)| id.trim().to_owned())
+}
+
+/// Shells out to `readelf -d` to read the `SONAME` dynamic-section entry (tag `DT_SONAME`)
+/// from a built shared library, e.g. `libfoo.so.1`. Returns `None` when the library carries
+/// no `DT_SONAME` (e.g. it wasn't linked with `-Wl,-soname=...`).
+fn read_soname(path: &Path) -> Option<String> {
+    let output = Command::new("readelf").arg("-d").arg(path).output().ok()?;
     if !output.status.success() {
-        return Err(CargoDebError::CommandError("cargo", "metadata".to_owned(), output.stderr));
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.lines()
+        .find_map(|line| line.split_once("Library soname: ["))
+        .and_then(|(_, rest)| rest.split_once(']'))
+        .map(|(soname, _)| soname.to_owned())
+}
+
+/// Shells out to git to capture the commit hash and working-tree cleanliness of `dir`.
+fn collect_git_info(dir: &Path) -> CDResult<VcsInfo> {
+    let rev_parse = Command::new("git").arg("rev-parse").arg("HEAD").current_dir(dir).output()
+        .map_err(|e| CargoDebError::CommandFailed(e, "git"))?;
+    if !rev_parse.status.success() {
+        return Err(CargoDebError::CommandError("git rev-parse", "HEAD".to_owned(), rev_parse.stderr));
     }
+    let commit = String::from_utf8_lossy(&rev_parse.stdout).trim().to_owned();
 
-    let stdout = String::from_utf8(output.stdout).unwrap();
-    let metadata = serde_json::from_str(&stdout)?;
-    Ok(metadata)
+    let status = Command::new("git").arg("status").arg("--porcelain").current_dir(dir).output()
+        .map_err(|e| CargoDebError::CommandFailed(e, "git"))?;
+    if !status.status.success() {
+        return Err(CargoDebError::CommandError("git status", "--porcelain".to_owned(), status.stderr));
+    }
+    let dirty = !status.stdout.is_empty();
+
+    Ok(VcsInfo { commit, dirty })
+}
+
+/// Debianizes the architecture name. Tries asking `rustc` what the target's `target_arch`/
+/// `target_env` actually are first, since that also covers custom/JSON target specs; falls back
+/// to guessing from the triple string if `rustc` isn't available or doesn't know the target.
+fn get_arch(target: &str) -> String {
+    if let Ok(info) = crate::rust_target::TargetInfo::detect(target) {
+        if !info.arch.is_empty() {
+            return info.debian_arch().to_owned();
+        }
+    }
+    get_arch_from_triple(target).to_owned()
 }
 
-/// Debianizes the architecture name
-fn get_arch(target: &str) -> &str {
+/// Guesses the Debian architecture name from the dash-separated components of a target triple,
+/// without needing to invoke `rustc`.
+fn get_arch_from_triple(target: &str) -> &str {
     let mut parts = target.split('-');
     let arch = parts.next().unwrap();
     let abi = parts.last().unwrap_or("");
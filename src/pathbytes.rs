@@ -12,14 +12,26 @@ impl AsUnixPathBytes for Path {
     fn as_unix_path(&self) -> Cow<[u8]> {
         use std::path::Component::*;
 
-        let parts: Vec<_> = self.components().filter_map(|c| {
+        let mut bytes = Vec::new();
+        for c in self.components() {
             match c {
-                Normal(c) => Some(c.to_str().expect("paths must be UTF-8").as_bytes()),
-                RootDir => Some(&b"/"[..]),
-                _ => None,
+                Normal(c) => {
+                    if !bytes.is_empty() && bytes.last() != Some(&b'/') {
+                        bytes.push(b'/');
+                    }
+                    // A valid Unicode component round-trips exactly; anything else (e.g. an
+                    // unpaired UTF-16 surrogate in a Windows path) falls back to a lossy
+                    // conversion instead of panicking, so an odd asset path can't abort the build.
+                    match c.to_str() {
+                        Some(s) => bytes.extend_from_slice(s.as_bytes()),
+                        None => bytes.extend_from_slice(c.to_string_lossy().as_bytes()),
+                    }
+                },
+                RootDir => bytes.push(b'/'),
+                _ => {},
             }
-        }).collect();
-        parts.join(&b'/').into()
+        }
+        bytes.into()
     }
 
     #[cfg(unix)]
@@ -32,3 +44,9 @@ impl AsUnixPathBytes for Path {
 fn unix_path() {
     assert_eq!(b"foo/bar/baz"[..], Path::new("foo/bar/baz").as_unix_path()[..]);
 }
+
+#[test]
+#[cfg(not(unix))]
+fn windows_path_separators_are_normalized() {
+    assert_eq!(b"foo/bar/baz"[..], Path::new(r"foo\bar\baz").as_unix_path()[..]);
+}
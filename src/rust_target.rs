@@ -0,0 +1,93 @@
+//! Maps a Rust target triple to the facts Debian packaging cares about, such as the
+//! `Architecture:` control field.
+
+use crate::error::*;
+use std::process::Command;
+
+/// `target_arch`/`target_os`/`target_env`/`target_abi`/`target_endian`, as reported by
+/// `rustc --print cfg --target <triple>`. More reliable than guessing from the triple's
+/// dash-separated components, since it also works for custom/JSON target specs whose triple
+/// string doesn't follow the usual convention.
+#[derive(Debug, Clone, Default)]
+pub struct TargetInfo {
+    pub arch: String,
+    pub os: String,
+    pub env: String,
+    pub abi: String,
+    pub endian: String,
+}
+
+impl TargetInfo {
+    /// Runs `rustc --print cfg --target <triple>` and parses out the `target_*` lines this
+    /// module cares about.
+    pub fn detect(target_triple: &str) -> CDResult<Self> {
+        let output = Command::new("rustc")
+            .args(&["--print", "cfg", "--target", target_triple])
+            .output()
+            .map_err(|e| CargoDebError::CommandFailed(e, "rustc"))?;
+        if !output.status.success() {
+            return Err(CargoDebError::CommandError("rustc --print cfg failed", target_triple.to_owned(), output.stderr));
+        }
+
+        let mut info = TargetInfo::default();
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if let Some(value) = line.strip_prefix("target_arch=") {
+                info.arch = unquote(value);
+            } else if let Some(value) = line.strip_prefix("target_os=") {
+                info.os = unquote(value);
+            } else if let Some(value) = line.strip_prefix("target_env=") {
+                info.env = unquote(value);
+            } else if let Some(value) = line.strip_prefix("target_abi=") {
+                info.abi = unquote(value);
+            } else if let Some(value) = line.strip_prefix("target_endian=") {
+                info.endian = unquote(value);
+            }
+        }
+        Ok(info)
+    }
+
+    /// Debianizes `target_arch`/`target_abi`/`target_endian`, following
+    /// <https://wiki.debian.org/Multiarch/Tuples>.
+    pub fn debian_arch(&self) -> &str {
+        match (self.arch.as_str(), self.abi.as_str()) {
+            ("aarch64", _) => "arm64",
+            ("x86_64", "x32") => "x32",
+            ("x86_64", _) => "amd64",
+            ("x86", _) => "i386",
+            ("arm", abi) if abi.ends_with("hf") => "armhf",
+            ("arm", _) => "armel",
+            ("powerpc64", _) if self.endian == "big" => "ppc64",
+            ("powerpc64", _) => "ppc64el",
+            ("mips64", "abin32") => "mipsn32",
+            ("mips64el", "abin32") => "mipsn32el",
+            (other, _) => other,
+        }
+    }
+}
+
+fn unquote(s: &str) -> String {
+    s.trim_matches('"').to_owned()
+}
+
+#[test]
+fn unquotes_cfg_values() {
+    assert_eq!("aarch64", unquote("\"aarch64\""));
+}
+
+#[test]
+fn debian_arch_mapping() {
+    let info = TargetInfo { arch: "aarch64".into(), os: "linux".into(), env: "gnu".into(), abi: String::new(), endian: "little".into() };
+    assert_eq!("arm64", info.debian_arch());
+
+    let info = TargetInfo { arch: "arm".into(), os: "linux".into(), env: "gnu".into(), abi: "eabihf".into(), endian: "little".into() };
+    assert_eq!("armhf", info.debian_arch());
+
+    let info = TargetInfo { arch: "riscv64".into(), os: "linux".into(), env: "gnu".into(), abi: String::new(), endian: "little".into() };
+    assert_eq!("riscv64", info.debian_arch());
+
+    let info = TargetInfo { arch: "powerpc64".into(), os: "linux".into(), env: "gnu".into(), abi: "elfv2".into(), endian: "little".into() };
+    assert_eq!("ppc64el", info.debian_arch());
+
+    let info = TargetInfo { arch: "powerpc64".into(), os: "linux".into(), env: "gnu".into(), abi: "elfv1".into(), endian: "big".into() };
+    assert_eq!("ppc64", info.debian_arch());
+}
@@ -1,26 +1,147 @@
 use crate::error::*;
+use crate::manifest::Owner;
+use crate::pathbytes::AsUnixPathBytes;
 use std::collections::HashSet;
-use std::io;
+use std::io::{self, Write};
 use std::path::{Component, Path, PathBuf};
 use tar::EntryType;
 use tar::Header as TarHeader;
 
-pub struct Archive {
+/// Ustar (and so also GNU) headers store `name`/`linkname` in 100-byte fields; paths that don't
+/// fit need a long-name extension entry instead (see [`Archive::set_path`]/[`Archive::set_link_name`]).
+const USTAR_NAME_LIMIT: usize = 100;
+
+/// Shortens `path` by dropping leading components until what's left fits the ustar name field,
+/// for use as the fallback name on a header that's preceded by a GNU long-name extension entry
+/// (which is what PAX/ustar-unaware readers ignoring the extension would actually see).
+fn shorten_for_ustar(path: &Path) -> PathBuf {
+    let mut candidate = path.to_path_buf();
+    while candidate.as_unix_path().len() >= USTAR_NAME_LIMIT {
+        let mut comps = candidate.components();
+        if comps.next().is_none() {
+            break;
+        }
+        let rest: PathBuf = comps.collect();
+        if rest.as_os_str().is_empty() {
+            break;
+        }
+        candidate = rest;
+    }
+    candidate
+}
+
+/// Sets the tar header's uid/gid/uname/gname, defaulting to `root:root` unless `owner` overrides it.
+fn set_owner(header: &mut TarHeader, owner: Option<&Owner>) -> io::Result<()> {
+    match owner {
+        Some(owner) => {
+            header.set_uid(owner.uid as u64);
+            header.set_gid(owner.gid as u64);
+            header.set_username(&owner.uname)?;
+            header.set_groupname(&owner.gname)?;
+        },
+        None => {
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_username("root")?;
+            header.set_groupname("root")?;
+        },
+    }
+    Ok(())
+}
+
+/// Builds one POSIX PAX extended header record, `"<len> SCHILY.xattr.<name>=<value>\n"`, where
+/// `<len>` (the record's own byte length, including itself) is found by the usual self-referential
+/// search since its digit count depends on the length it's describing. `value` is written as raw
+/// bytes, not escaped, since PAX records allow arbitrary binary content after the `=`.
+fn pax_xattr_record(name: &str, value: &[u8]) -> Vec<u8> {
+    let key = format!("SCHILY.xattr.{}", name);
+    // " " + key + "=" + value + "\n"
+    let fixed_len = 1 + key.len() + 1 + value.len() + 1;
+    let mut len = fixed_len + 1;
+    loop {
+        let total = fixed_len + len.to_string().len();
+        if total == len {
+            break;
+        }
+        len = total;
+    }
+    let mut record = Vec::with_capacity(len);
+    record.extend_from_slice(len.to_string().as_bytes());
+    record.push(b' ');
+    record.extend_from_slice(key.as_bytes());
+    record.push(b'=');
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+/// A tar archive being built, writing each entry straight into `W` as it's appended rather than
+/// buffering the whole archive in memory. Callers that need the final bytes resident anyway
+/// (e.g. to hand off to a compressor, or to an `ar` member that needs its size upfront) pass
+/// `Vec<u8>`; callers compressing on the fly pass the compressor's writer directly.
+pub struct Archive<W: Write> {
     added_directories: HashSet<PathBuf>,
     time: u64,
-    tar: tar::Builder<Vec<u8>>,
+    tar: tar::Builder<W>,
 }
 
-impl Archive {
-    pub fn new(time: u64) -> Self {
+impl<W: Write> Archive<W> {
+    pub fn new(time: u64, writer: W) -> Self {
         Self {
             added_directories: HashSet::new(),
             time,
-            tar: tar::Builder::new(Vec::new()),
+            tar: tar::Builder::new(writer),
         }
     }
 
-    fn directory(&mut self, path: &Path) -> io::Result<()> {
+    /// Writes a GNU long-name (`typeflag` `L`) or long-link (`K`) extension entry recording the
+    /// full `bytes`, which the entry written immediately afterwards refers back to instead of
+    /// relying on its own (possibly truncated) `name`/`linkname` field.
+    fn append_gnu_long(&mut self, entry_type: EntryType, bytes: &[u8]) -> CDResult<()> {
+        let mut header = TarHeader::new_gnu();
+        header.set_mtime(self.time);
+        header.set_entry_type(entry_type);
+        header.set_mode(0o644);
+        header.set_size(bytes.len() as u64 + 1); // + the NUL terminator GNU tar expects
+        header.set_path("././@LongLink")?;
+        set_owner(&mut header, None)?;
+        header.set_cksum();
+        let mut data = bytes.to_vec();
+        data.push(0);
+        self.tar.append(&header, &data[..])?;
+        Ok(())
+    }
+
+    /// Sets `header`'s path, falling back to a GNU long-name extension entry (plus a shortened
+    /// name on `header` itself, for readers that don't understand the extension) when `path`
+    /// doesn't fit the ustar/GNU 100-byte name field.
+    fn set_path<P: AsRef<Path>>(&mut self, header: &mut TarHeader, path: P) -> CDResult<()> {
+        let path = path.as_ref();
+        if let Err(err) = header.set_path(path) {
+            if path.as_unix_path().len() < USTAR_NAME_LIMIT {
+                return Err(err.into());
+            }
+            self.append_gnu_long(EntryType::GNULongName, &path.as_unix_path())?;
+            header.set_path(shorten_for_ustar(path))?;
+        }
+        Ok(())
+    }
+
+    /// Sets `header`'s link target, falling back to a GNU long-link extension entry the same way
+    /// [`Archive::set_path`] does for the name, when `link_name` is too long for the header field.
+    fn set_link_name<P: AsRef<Path>>(&mut self, header: &mut TarHeader, link_name: P) -> CDResult<()> {
+        let link_name = link_name.as_ref();
+        if let Err(err) = header.set_link_name(link_name) {
+            if link_name.as_unix_path().len() < USTAR_NAME_LIMIT {
+                return Err(err.into());
+            }
+            self.append_gnu_long(EntryType::GNULongLink, &link_name.as_unix_path())?;
+            header.set_link_name(shorten_for_ustar(link_name))?;
+        }
+        Ok(())
+    }
+
+    fn directory(&mut self, path: &Path) -> CDResult<()> {
         let mut header = TarHeader::new_gnu();
         header.set_mtime(self.time);
         header.set_size(0);
@@ -30,10 +151,12 @@ impl Archive {
         if !path_str.ends_with('/') {
             path_str += "/";
         }
-        header.set_path(&path_str)?;
+        self.set_path(&mut header, &path_str)?;
         header.set_entry_type(EntryType::Directory);
+        set_owner(&mut header, None)?;
         header.set_cksum();
-        self.tar.append(&header, &mut io::empty())
+        self.tar.append(&header, &mut io::empty())?;
+        Ok(())
     }
 
     fn add_parent_directories(&mut self, path: &Path) -> CDResult<()> {
@@ -56,34 +179,94 @@ impl Archive {
     }
 
     pub fn file<P: AsRef<Path>>(&mut self, path: P, out_data: &[u8], chmod: u32) -> CDResult<()> {
+        self.file_with_owner(path, out_data, chmod, None)
+    }
+
+    pub fn file_with_owner<P: AsRef<Path>>(&mut self, path: P, out_data: &[u8], chmod: u32, owner: Option<&Owner>) -> CDResult<()> {
+        self.file_with_xattrs(path, out_data, chmod, owner, &[])
+    }
+
+    /// Like [`Archive::file_with_owner`], but also attaches each `(name, value)` pair in
+    /// `xattrs` as an extended attribute (e.g. `("security.capability", <encoded caps>)`), via a
+    /// PAX extended header record immediately preceding the entry.
+    pub fn file_with_xattrs<P: AsRef<Path>>(&mut self, path: P, out_data: &[u8], chmod: u32, owner: Option<&Owner>, xattrs: &[(&str, &[u8])]) -> CDResult<()> {
         self.add_parent_directories(path.as_ref())?;
 
+        if !xattrs.is_empty() {
+            self.append_pax_extension(xattrs)?;
+        }
+
         let mut header = TarHeader::new_gnu();
         header.set_mtime(self.time);
-        header.set_path(path)?;
+        self.set_path(&mut header, path)?;
         header.set_mode(chmod);
         header.set_size(out_data.len() as u64);
+        set_owner(&mut header, owner)?;
         header.set_cksum();
         self.tar.append(&header, out_data)?;
         Ok(())
     }
 
+    /// Writes a POSIX PAX extended header entry, a `SCHILY.xattr.<name>=<value>` record per
+    /// entry in `xattrs`, immediately preceding (and applying to) the next entry appended.
+    fn append_pax_extension(&mut self, xattrs: &[(&str, &[u8])]) -> CDResult<()> {
+        let mut records = Vec::new();
+        for (name, value) in xattrs {
+            records.extend(pax_xattr_record(name, value));
+        }
+
+        let mut header = TarHeader::new_gnu();
+        header.set_mtime(self.time);
+        header.set_entry_type(EntryType::XHeader);
+        header.set_mode(0o644);
+        header.set_size(records.len() as u64);
+        header.set_path("./PaxHeaders.0/xattrs")?;
+        set_owner(&mut header, None)?;
+        header.set_cksum();
+        self.tar.append(&header, &records[..])?;
+        Ok(())
+    }
+
     pub fn symlink<P: AsRef<Path>>(&mut self, path: P, link_name: P) -> CDResult<()> {
+        self.symlink_with_owner(path, link_name, None)
+    }
+
+    /// Archives `path` as a tar hardlink to `link_name`, an earlier entry with identical content,
+    /// instead of storing (and compressing) the bytes again. `chmod`/`owner` are carried on the
+    /// link entry itself, same as for [`Archive::file_with_owner`].
+    pub fn hardlink<P: AsRef<Path>>(&mut self, path: P, link_name: P, chmod: u32, owner: Option<&Owner>) -> CDResult<()> {
+        self.add_parent_directories(path.as_ref())?;
+
+        let mut header = TarHeader::new_gnu();
+        header.set_mtime(self.time);
+        header.set_entry_type(EntryType::Link);
+        self.set_path(&mut header, &path)?;
+        self.set_link_name(&mut header, &link_name)?;
+        header.set_size(0);
+        header.set_mode(chmod);
+        set_owner(&mut header, owner)?;
+        header.set_cksum();
+        self.tar.append(&header, &mut io::empty())?;
+        Ok(())
+    }
+
+    pub fn symlink_with_owner<P: AsRef<Path>>(&mut self, path: P, link_name: P, owner: Option<&Owner>) -> CDResult<()> {
         self.add_parent_directories(path.as_ref())?;
 
         let mut header = TarHeader::new_gnu();
         header.set_mtime(self.time);
         header.set_entry_type(EntryType::Symlink);
-        header.set_path(&path)?;
-        header.set_link_name(&link_name)?;
+        self.set_path(&mut header, &path)?;
+        self.set_link_name(&mut header, &link_name)?;
         header.set_size(0);
         header.set_mode(0o777);
+        set_owner(&mut header, owner)?;
         header.set_cksum();
         self.tar.append(&header, &mut io::empty())?;
         Ok(())
     }
 
-    pub fn into_inner(self) -> io::Result<Vec<u8>> {
+    pub fn into_inner(self) -> io::Result<W> {
         self.tar.into_inner()
     }
 }
@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+/// Tracks paths a build is in the middle of creating (the staged temp directory, the final
+/// `.deb`), removing them on drop unless [`Transaction::commit`] runs first. This way a failure
+/// partway through `cargo deb` doesn't leave a half-written `.deb` or a stale temp directory
+/// behind for the next run to trip over.
+#[derive(Default)]
+pub struct Transaction {
+    paths: Vec<PathBuf>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `path` for removal if the transaction is dropped without being committed.
+    pub fn track(&mut self, path: PathBuf) {
+        self.paths.push(path);
+    }
+
+    /// Marks the transaction as successful, so its tracked paths are left alone.
+    pub fn commit(mut self) {
+        self.paths.clear();
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        for path in self.paths.drain(..) {
+            let _ = crate::remove_dir_all_writeable(&path);
+        }
+    }
+}
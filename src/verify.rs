@@ -0,0 +1,162 @@
+//! `cargo deb --verify`: re-opens a just-built `.deb` and checks it's actually well-formed,
+//! mirroring the sanity check `cargo package`/`cargo install` run before declaring success. A
+//! broken codec, a bug in `tararchive`, or a race on the output path could otherwise silently
+//! ship a `.deb` that `dpkg` can't install.
+
+use crate::error::*;
+use crate::listener::Listener;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Runs every check in this module against `deb_path`, failing on the first one that doesn't
+/// hold. Best-effort checks (`dpkg-deb`/`lintian`, when present on `PATH`) only produce warnings,
+/// since their absence doesn't mean the `.deb` itself is broken.
+pub fn verify(deb_path: &Path, listener: &mut dyn Listener) -> CDResult<()> {
+    let members = read_ar_members(deb_path)?;
+    check_member_order(deb_path, &members)?;
+
+    let control_tar = decompress_member(&members[1].0, &members[1].1)?;
+    let data_tar = decompress_member(&members[2].0, &members[2].1)?;
+
+    let md5sums = read_md5sums(deb_path, &control_tar)?;
+    check_data_hashes(deb_path, &data_tar, &md5sums)?;
+
+    run_best_effort_check(deb_path, "dpkg-deb", &["--contents"], listener);
+    run_best_effort_check(deb_path, "lintian", &[], listener);
+
+    listener.info(format!("verified {}", deb_path.display()));
+    Ok(())
+}
+
+/// Reads every entry out of the outer `ar` container, as `(member name, raw bytes)` pairs, in
+/// on-disk order.
+fn read_ar_members(deb_path: &Path) -> CDResult<Vec<(String, Vec<u8>)>> {
+    let file = File::open(deb_path)?;
+    let mut archive = ar::Archive::new(file);
+    let mut members = Vec::new();
+    while let Some(entry) = archive.next_entry() {
+        let mut entry = entry?;
+        let name = String::from_utf8_lossy(entry.header().identifier()).into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        members.push((name, data));
+    }
+    Ok(members)
+}
+
+/// Debian requires `debian-binary`, then the control archive, then the data archive, in that
+/// exact order, so `dpkg` can stop reading as soon as it has what it needs.
+fn check_member_order(deb_path: &Path, members: &[(String, Vec<u8>)]) -> CDResult<()> {
+    let names: Vec<&str> = members.iter().map(|(name, _)| name.as_str()).collect();
+    let ok = names.len() == 3
+        && names[0] == "debian-binary"
+        && members[0].1 == b"2.0\n"
+        && names[1].starts_with("control.tar")
+        && names[2].starts_with("data.tar");
+    if !ok {
+        return Err(CargoDebError::VerificationFailed(format!(
+            "{}: expected [debian-binary, control.tar.*, data.tar.*], found {:?}",
+            deb_path.display(), names
+        )));
+    }
+    Ok(())
+}
+
+fn decompress_member(member_name: &str, data: &[u8]) -> CDResult<Vec<u8>> {
+    let mut out = Vec::new();
+    if member_name.ends_with(".gz") {
+        flate2::read::GzDecoder::new(data).read_to_end(&mut out)?;
+    } else if member_name.ends_with(".xz") {
+        decompress_xz(data, &mut out)?;
+    } else if member_name.ends_with(".zst") {
+        decompress_zst(data, &mut out)?;
+    } else {
+        out.extend_from_slice(data);
+    }
+    Ok(out)
+}
+
+#[cfg(feature = "lzma")]
+fn decompress_xz(data: &[u8], out: &mut Vec<u8>) -> CDResult<()> {
+    xz2::read::XzDecoder::new(data).read_to_end(out)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "lzma"))]
+fn decompress_xz(_data: &[u8], _out: &mut Vec<u8>) -> CDResult<()> {
+    Err(CargoDebError::Str("cargo-deb was built without xz support, so an .xz member can't be verified"))
+}
+
+#[cfg(feature = "zstd")]
+fn decompress_zst(data: &[u8], out: &mut Vec<u8>) -> CDResult<()> {
+    *out = zstd::decode_all(data).map_err(CargoDebError::ZstdCompressionError)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress_zst(_data: &[u8], _out: &mut Vec<u8>) -> CDResult<()> {
+    Err(CargoDebError::Str("cargo-deb was built without zstd support, so a .zst member can't be verified"))
+}
+
+/// Parses `control.tar`'s `./md5sums` file into `{target path => recorded digest}`, the same
+/// format [`crate::control::generate_archive`] wrote it in.
+fn read_md5sums(deb_path: &Path, control_tar: &[u8]) -> CDResult<HashMap<PathBuf, String>> {
+    let mut tar = tar::Archive::new(control_tar);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.to_string_lossy() != "./md5sums" {
+            continue;
+        }
+        let mut contents = String::new();
+        entry.read_to_string(&mut contents)?;
+        return Ok(contents.lines()
+            .filter_map(|line| line.split_once("  "))
+            .map(|(digest, path)| (PathBuf::from(path), digest.to_owned()))
+            .collect());
+    }
+    Err(CargoDebError::VerificationFailed(format!("{}: control.tar has no md5sums file", deb_path.display())))
+}
+
+/// Recomputes the md5 of every regular file in `data.tar` and checks it against the digest
+/// `md5sums` recorded for that path, catching a codec bug or truncated write that corrupted the
+/// packaged file without corrupting the archive framing around it.
+fn check_data_hashes(deb_path: &Path, data_tar: &[u8], md5sums: &HashMap<PathBuf, String>) -> CDResult<()> {
+    let mut tar = tar::Archive::new(data_tar);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        let expected = match md5sums.get(&path) {
+            Some(digest) => digest,
+            None => continue, // not every data.tar entry is necessarily hashed (e.g. future asset kinds)
+        };
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        let actual = format!("{:x}", md5::compute(&contents));
+        if &actual != expected {
+            return Err(CargoDebError::VerificationFailed(format!(
+                "{}: {} md5 mismatch (control.tar says {}, data.tar has {})",
+                deb_path.display(), path.display(), expected, actual
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Shells out to `tool` (`dpkg-deb --contents` or `lintian`) if it's on `PATH`; a non-zero exit
+/// only produces a warning, since these reflect Debian packaging policy/lintian taste rather than
+/// whether the `.deb` cargo-deb wrote is structurally valid.
+fn run_best_effort_check(deb_path: &Path, tool: &str, extra_args: &[&str], listener: &mut dyn Listener) {
+    let output = match Command::new(tool).args(extra_args).arg(deb_path).output() {
+        Ok(output) => output,
+        Err(_) => return, // tool isn't installed; nothing to report
+    };
+    if !output.status.success() {
+        listener.warning(format!("{} flagged {}: {}", tool, deb_path.display(), String::from_utf8_lossy(&output.stderr)));
+    }
+}
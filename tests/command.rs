@@ -151,3 +151,164 @@ fn run_cargo_deb_command_on_example_dir_with_variant() {
     assert!(ddir.path().join("usr/share/doc/example-debug/changelog.gz").exists());
     assert!(ddir.path().join("usr/bin/example").exists());
 }
+
+/// `--deterministic` plus a fixed `SOURCE_DATE_EPOCH` must make two builds of the same source
+/// tree produce byte-for-byte identical `.deb`s, even when the wall clock and local timezone
+/// differ between the two runs.
+#[test]
+#[cfg(all(feature = "lzma", target_os = "linux"))]
+fn reproducible_build_is_byte_for_byte_identical() {
+    let root = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let cmd_path = root.join("target/debug/cargo-deb");
+    assert!(cmd_path.exists());
+
+    let build = |tz: &str, target_dir: &Path, out_path: &Path| {
+        let output = Command::new(&cmd_path)
+            .env("CARGO_TARGET_DIR", target_dir)
+            .env("TZ", tz)
+            .env("SOURCE_DATE_EPOCH", "1000000000")
+            .arg("--deterministic")
+            .arg("--no-strip")
+            .arg(format!("--output={}", out_path.display()))
+            .arg(format!("--manifest-path={}", root.join("example/Cargo.toml").display()))
+            .output().unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    };
+
+    let first_dir = TempDir::new("cargo-deb-repro-1").unwrap();
+    let first_deb = first_dir.path().join("first.deb");
+    build("UTC", first_dir.path(), &first_deb);
+
+    let second_dir = TempDir::new("cargo-deb-repro-2").unwrap();
+    let second_deb = second_dir.path().join("second.deb");
+    build("America/New_York", second_dir.path(), &second_deb);
+
+    assert_eq!(fs::read(&first_deb).unwrap(), fs::read(&second_deb).unwrap());
+}
+
+/// With `hardlink-dedup` enabled, assets sharing identical content (`var/lib/example/dup-a.txt`
+/// and `var/lib/example/dup-b.txt`, configured in `example/Cargo.toml` to be copies of the same
+/// source file) must still both be present and byte-identical after extraction, even though only
+/// one of them is stored in full in `data.tar` and the other is a tar hardlink to it.
+#[test]
+#[cfg(all(feature = "lzma", target_os = "linux"))]
+fn hardlink_deduped_assets_round_trip() {
+    let root = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let cmd_path = root.join("target/debug/cargo-deb");
+    assert!(cmd_path.exists());
+
+    let cargo_dir = TempDir::new("cargo-deb-hardlink-dedup").unwrap();
+    let deb_path = cargo_dir.path().join("test.deb");
+    let output = Command::new(&cmd_path)
+        .env("CARGO_TARGET_DIR", cargo_dir.path())
+        .arg("--no-strip")
+        .arg(format!("--output={}", deb_path.display()))
+        .arg(format!("--manifest-path={}", root.join("example/Cargo.toml").display()))
+        .output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let ardir = TempDir::new("cargo-deb-hardlink-dedup-ar").unwrap();
+    assert!(Command::new("ar")
+        .current_dir(ardir.path())
+        .arg("-x")
+        .arg(&deb_path)
+        .status().unwrap().success());
+
+    let ddir = TempDir::new("cargo-deb-hardlink-dedup-data").unwrap();
+    assert!(Command::new("tar")
+        .arg("xJf")
+        .current_dir(ddir.path())
+        .arg(ardir.path().join("data.tar.xz"))
+        .status().unwrap().success());
+
+    let dup_a = fs::read(ddir.path().join("var/lib/example/dup-a.txt")).unwrap();
+    let dup_b = fs::read(ddir.path().join("var/lib/example/dup-b.txt")).unwrap();
+    assert_eq!(dup_a, dup_b);
+}
+
+/// An asset configured with `"cap_net_bind_service+ep"` (`example/Cargo.toml`'s `usr/bin/example`
+/// entry) must carry a `security.capability` xattr that round-trips through extraction, so the
+/// binary can bind privileged ports without a postinst `setcap` call.
+#[test]
+#[cfg(all(feature = "lzma", target_os = "linux"))]
+fn capability_xattr_is_embedded_and_extracted() {
+    let root = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let cmd_path = root.join("target/debug/cargo-deb");
+    assert!(cmd_path.exists());
+
+    let cargo_dir = TempDir::new("cargo-deb-capabilities").unwrap();
+    let deb_path = cargo_dir.path().join("test.deb");
+    let output = Command::new(&cmd_path)
+        .env("CARGO_TARGET_DIR", cargo_dir.path())
+        .arg("--no-strip")
+        .arg(format!("--output={}", deb_path.display()))
+        .arg(format!("--manifest-path={}", root.join("example/Cargo.toml").display()))
+        .output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let ardir = TempDir::new("cargo-deb-capabilities-ar").unwrap();
+    assert!(Command::new("ar")
+        .current_dir(ardir.path())
+        .arg("-x")
+        .arg(&deb_path)
+        .status().unwrap().success());
+
+    let ddir = TempDir::new("cargo-deb-capabilities-data").unwrap();
+    assert!(Command::new("tar")
+        .arg("--xattrs")
+        .arg("xJf")
+        .current_dir(ddir.path())
+        .arg(ardir.path().join("data.tar.xz"))
+        .status().unwrap().success());
+
+    let output = Command::new("getfattr")
+        .arg("-n").arg("security.capability")
+        .arg("--only-values")
+        .arg(ddir.path().join("usr/bin/example"))
+        .output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // VFS_CAP_REVISION_2 with the effective flag set, little-endian.
+    assert_eq!(&[0x01, 0x00, 0x00, 0x02], &output.stdout[..4]);
+}
+
+/// An asset installed at a destination path over 100 bytes long (ustar's classic `name` field
+/// limit) must still round-trip through extraction with its exact path intact, via a GNU
+/// long-name extension entry instead of being truncated or rejected.
+#[test]
+#[cfg(all(feature = "lzma", target_os = "linux"))]
+fn long_install_path_round_trips() {
+    let root = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let cmd_path = root.join("target/debug/cargo-deb");
+    assert!(cmd_path.exists());
+
+    // `example/Cargo.toml` installs one asset under this path, deliberately over 100 bytes long.
+    let long_path = "var/lib/example/a-deeply-nested-directory-structure/that-is-constructed/\
+                      specifically-to-exceed-the-classic-ustar-one-hundred-byte-name-field-limit/long.txt";
+    assert!(long_path.len() > 100);
+
+    let cargo_dir = TempDir::new("cargo-deb-long-path").unwrap();
+    let deb_path = cargo_dir.path().join("test.deb");
+    let output = Command::new(&cmd_path)
+        .env("CARGO_TARGET_DIR", cargo_dir.path())
+        .arg("--no-strip")
+        .arg(format!("--output={}", deb_path.display()))
+        .arg(format!("--manifest-path={}", root.join("example/Cargo.toml").display()))
+        .output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let ardir = TempDir::new("cargo-deb-long-path-ar").unwrap();
+    assert!(Command::new("ar")
+        .current_dir(ardir.path())
+        .arg("-x")
+        .arg(&deb_path)
+        .status().unwrap().success());
+
+    let ddir = TempDir::new("cargo-deb-long-path-data").unwrap();
+    assert!(Command::new("tar")
+        .arg("xJf")
+        .current_dir(ddir.path())
+        .arg(ardir.path().join("data.tar.xz"))
+        .status().unwrap().success());
+
+    assert!(ddir.path().join(long_path).exists(), "long install path wasn't reproduced exactly by extraction");
+}